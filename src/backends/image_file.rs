@@ -0,0 +1,66 @@
+use crate::backends::{Backend, Input};
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+use std::error::Error;
+
+/*
+    Writes frames to image files, the format follows the file extension.
+    PNGs keep the alpha channel, so a transparent bg_color gives a transparent image.
+
+    A "{frame}" in the path is replaced by the frame number, otherwise the
+    same file is overwritten every frame. Closes itself after max_frames frames.
+*/
+pub struct ImageBackend {
+    pub path: String,
+    pub max_frames: usize,
+    pub frames_written: usize,
+}
+
+impl ImageBackend {
+    pub fn new(path: &str, max_frames: usize) -> Self {
+        Self {
+            path: path.to_string(),
+            max_frames,
+            frames_written: 0,
+        }
+    }
+
+    pub fn frame_path(&self, frame: usize) -> String {
+        self.path.replace("{frame}", &format!("{:04}", frame))
+    }
+}
+
+impl Backend for ImageBackend {
+    fn is_open(&self) -> bool {
+        self.frames_written < self.max_frames
+    }
+
+    fn poll_input(&mut self) -> Input {
+        Input::default()
+    }
+
+    fn present(
+        &mut self,
+        buffer: &[u32],
+        size_x: usize,
+        size_y: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let image = RgbaImage::from_fn(size_x as u32, size_y as u32, |x, y| {
+            let val = buffer[y as usize * size_x + x as usize];
+            Rgba([
+                (val >> 16) as u8,
+                (val >> 8) as u8,
+                val as u8,
+                (val >> 24) as u8,
+            ])
+        });
+        let path = self.frame_path(self.frames_written);
+        // formats without an alpha channel get the colors only
+        if ImageFormat::from_path(&path)? == ImageFormat::Jpeg {
+            DynamicImage::ImageRgba8(image).to_rgb8().save(&path)?;
+        } else {
+            image.save(&path)?;
+        }
+        self.frames_written += 1;
+        Ok(())
+    }
+}
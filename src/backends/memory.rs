@@ -0,0 +1,63 @@
+use crate::backends::{Backend, Input};
+use std::collections::VecDeque;
+use std::error::Error;
+
+/*
+    Keeps every presented frame in memory, e.g. for tests.
+
+    Inputs queued with push_input are handed out one per frame, the backend
+    closes itself after max_frames frames.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBackend {
+    pub frames: Vec<Vec<u32>>,
+    pub size_x: usize,
+    pub size_y: usize,
+    pub max_frames: usize,
+    pub status: Vec<String>,
+
+    inputs: VecDeque<Input>,
+}
+
+impl MemoryBackend {
+    pub fn new(max_frames: usize) -> Self {
+        Self {
+            max_frames,
+            ..Default::default()
+        }
+    }
+
+    pub fn push_input(&mut self, input: Input) {
+        self.inputs.push_back(input);
+    }
+
+    pub fn last_frame(&self) -> Option<&Vec<u32>> {
+        self.frames.last()
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn is_open(&self) -> bool {
+        self.frames.len() < self.max_frames
+    }
+
+    fn poll_input(&mut self) -> Input {
+        self.inputs.pop_front().unwrap_or_default()
+    }
+
+    fn present(
+        &mut self,
+        buffer: &[u32],
+        size_x: usize,
+        size_y: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        self.frames.push(buffer.to_vec());
+        self.size_x = size_x;
+        self.size_y = size_y;
+        Ok(())
+    }
+
+    fn show_status(&mut self, lines: &[String]) {
+        self.status = lines.to_vec();
+    }
+}
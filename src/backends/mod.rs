@@ -0,0 +1,88 @@
+mod image_file;
+mod memory;
+mod terminal;
+#[cfg(feature = "viewer")]
+mod window;
+
+use std::error::Error;
+
+// Re-export so callers can write `backends::WindowBackend` directly
+pub use image_file::ImageBackend;
+pub use memory::MemoryBackend;
+pub use terminal::{TerminalBackend, TerminalMode};
+#[cfg(feature = "viewer")]
+pub use window::WindowBackend;
+
+/*
+    Backend-agnostic presentation and input.
+
+    A backend receives the resolved Canvas::buffer once per frame and reports the
+    user input that happened since the previous frame.
+*/
+pub trait Backend {
+    fn is_open(&self) -> bool;
+
+    fn poll_input(&mut self) -> Input;
+
+    fn present(
+        &mut self,
+        buffer: &[u32],
+        size_x: usize,
+        size_y: usize,
+    ) -> Result<(), Box<dyn Error>>;
+
+    // per frame statistics, ignored by backends without a place to show them
+    fn show_status(&mut self, _lines: &[String]) {}
+}
+
+/*
+    Keys as the backends report them, letters and digits come in as lowercase Char.
+    What a key does is up to the application, the library binds none of them.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InputKey {
+    Char(char),
+    Enter,
+    Space,
+    Escape,
+    Tab,
+    Backspace,
+    Up,
+    Down,
+    Left,
+    Right,
+    LeftShift,
+    RightShift,
+    LeftCtrl,
+    RightCtrl,
+    LeftAlt,
+    RightAlt,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InputMouseButton {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Input {
+    pub keys_down: Vec<InputKey>,
+    pub keys_pressed: Vec<InputKey>, // only newly pressed keys, no repeats
+    pub mouse_pos: Option<(f32, f32)>,
+    pub mouse_buttons_down: Vec<InputMouseButton>,
+}
+
+impl Input {
+    pub fn is_key_down(&self, key: InputKey) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    pub fn is_key_pressed(&self, key: InputKey) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn is_mouse_down(&self, button: InputMouseButton) -> bool {
+        self.mouse_buttons_down.contains(&button)
+    }
+}
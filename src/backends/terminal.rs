@@ -0,0 +1,288 @@
+use crate::backends::{Backend, Input};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/*
+    Draws a Canvas buffer into the terminal, e.g. for a live view over ssh.
+
+    In true color mode every character cell holds two pixels: the upper one as the
+    foreground color of a half block "▀", the lower one as its background color.
+    Terminals without 24-bit color get an ascii brightness ramp instead, one pixel per cell.
+    The image is letterboxed into the terminal, so it keeps the aspect ratio of the canvas.
+
+    There is no raw terminal mode, so this backend reports no input, stop it with ctrl-c.
+*/
+
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TerminalMode {
+    TrueColor,
+    Ascii,
+}
+
+impl TerminalMode {
+    pub fn detect() -> Self {
+        /*
+            ssh does not forward $COLORTERM by default, so $TERM is checked as well.
+            the viewer can force a mode with --truecolor / --ascii
+        */
+        let is_true_color = |value: String| {
+            ["truecolor", "24bit", "direct"]
+                .iter()
+                .any(|name| value.contains(name))
+        };
+        if env::var("COLORTERM").is_ok_and(is_true_color)
+            || env::var("TERM").is_ok_and(is_true_color)
+        {
+            TerminalMode::TrueColor
+        } else {
+            TerminalMode::Ascii
+        }
+    }
+}
+
+impl fmt::Display for TerminalMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TerminalMode::TrueColor => write!(f, "24-bit half blocks"),
+            TerminalMode::Ascii => write!(f, "ascii ramp"),
+        }
+    }
+}
+
+pub struct TerminalBackend {
+    pub cols: usize,
+    pub rows: usize,
+    pub mode: TerminalMode,
+
+    first_frame: bool,
+}
+
+impl TerminalBackend {
+    pub fn new(cols: usize, rows: usize, mode: TerminalMode) -> Self {
+        // at least one cell, so there is always room for a pixel
+        Self {
+            cols: cols.max(1),
+            rows: rows.max(1),
+            mode,
+            first_frame: true,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        /*
+            size of the terminal window, asked from the terminal itself, then `stty size`,
+            then $COLUMNS / $LINES (which shells rarely export) and 80x24 as the last resort.
+            one line is kept free for the status line
+        */
+        let (cols, rows) = query_window_size()
+            .or_else(query_stty_size)
+            .or_else(|| {
+                let var = |name: &str| env::var(name).ok()?.parse::<usize>().ok();
+                Some((var("COLUMNS")?, var("LINES")?))
+            })
+            .unwrap_or((80, 24));
+        Self::new(cols, rows.saturating_sub(1).max(1), TerminalMode::detect())
+    }
+
+    pub fn pixel_size(&self, size_x: usize, size_y: usize) -> (usize, usize) {
+        /*
+            largest image with the aspect ratio of the canvas that fits into the terminal,
+            in pixels of the current mode. a cell is about twice as tall as it is wide, so it
+            holds two square pixels in true color mode and one tall pixel in ascii mode
+        */
+        // the fields are public, a size of 0 is treated as 1 here as well
+        let (cols, rows) = (self.cols.max(1), self.rows.max(1));
+        let aspect = size_x as f64 / size_y.max(1) as f64;
+        let max_half_rows = (rows * 2) as f64;
+        let (mut width, mut half_rows) = (cols as f64, cols as f64 / aspect);
+        if half_rows > max_half_rows {
+            half_rows = max_half_rows;
+            width = half_rows * aspect;
+        }
+        let width = (width.round() as usize).clamp(1, cols);
+        let half_rows = (half_rows.round() as usize).clamp(1, rows * 2);
+        match self.mode {
+            TerminalMode::TrueColor => (width, half_rows),
+            TerminalMode::Ascii => (width, half_rows.div_ceil(2)),
+        }
+    }
+
+    pub fn render_to_string(&self, buffer: &[u32], size_x: usize, size_y: usize) -> String {
+        let (target_x, target_y) = self.pixel_size(size_x, size_y);
+        let image_rows = match self.mode {
+            TerminalMode::TrueColor => target_y.div_ceil(2),
+            TerminalMode::Ascii => target_y,
+        };
+        // centered, the bars around the image stay in the terminal's own background
+        let offset_col = self.cols.saturating_sub(target_x) / 2;
+        let offset_row = self.rows.saturating_sub(image_rows) / 2;
+
+        // nearest neighbour sampling of the canvas buffer, None below the image
+        let sample = |x: usize, y: usize| -> Option<u32> {
+            if y >= target_y {
+                return None;
+            }
+            let src_x = (x * size_x / target_x).min(size_x - 1);
+            let src_y = (y * size_y / target_y).min(size_y - 1);
+            Some(buffer[src_y * size_x + src_x])
+        };
+
+        let mut out = String::with_capacity(self.cols * self.rows * 24);
+        for row in 0..self.rows {
+            if row < offset_row || row >= offset_row + image_rows {
+                out.push_str("\x1b[K\n");
+                continue;
+            }
+            let row = row - offset_row;
+            out.push_str(&" ".repeat(offset_col));
+            let mut prev_colors: Option<(u32, Option<u32>)> = None;
+            for col in 0..target_x {
+                match self.mode {
+                    TerminalMode::TrueColor => {
+                        let upper = sample(col, row * 2).unwrap_or(0);
+                        let lower = sample(col, row * 2 + 1);
+                        if prev_colors != Some((upper, lower)) {
+                            let (r_u, g_u, b_u) = split_rgb(upper);
+                            let _ = write!(out, "\x1b[38;2;{};{};{}", r_u, g_u, b_u);
+                            match lower {
+                                Some(lower) => {
+                                    let (r_l, g_l, b_l) = split_rgb(lower);
+                                    let _ = write!(out, ";48;2;{};{};{}m", r_l, g_l, b_l);
+                                }
+                                // odd pixel count, the last lower half stays empty
+                                None => out.push_str(";49m"),
+                            }
+                            prev_colors = Some((upper, lower));
+                        }
+                        out.push('▀');
+                    }
+                    TerminalMode::Ascii => {
+                        let (r, g, b) = split_rgb(sample(col, row).unwrap_or(0));
+                        let luminance =
+                            (0.2126 * r as f64 + 0.7152 * g as f64 + 0.0722 * b as f64) / 255.0;
+                        let idx = (luminance * (ASCII_RAMP.len() - 1) as f64).round() as usize;
+                        out.push(ASCII_RAMP[idx.min(ASCII_RAMP.len() - 1)] as char);
+                    }
+                }
+            }
+            if self.mode == TerminalMode::TrueColor {
+                out.push_str("\x1b[0m");
+            }
+            // erase leftovers of a previous, wider frame
+            out.push_str("\x1b[K\n");
+        }
+        out
+    }
+}
+
+impl Backend for TerminalBackend {
+    fn is_open(&self) -> bool {
+        true
+    }
+
+    fn poll_input(&mut self) -> Input {
+        Input::default()
+    }
+
+    fn present(
+        &mut self,
+        buffer: &[u32],
+        size_x: usize,
+        size_y: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        /*
+            redraws in place: cursor home instead of clearing, so frames don't flicker
+        */
+        let frame = self.render_to_string(buffer, size_x, size_y);
+
+        let stdout = io::stdout();
+        let mut handle = stdout.lock();
+        if self.first_frame {
+            write!(handle, "\x1b[2J")?;
+            self.first_frame = false;
+        }
+        write!(handle, "\x1b[H{}", frame)?;
+        handle.flush()?;
+        Ok(())
+    }
+
+    fn show_status(&mut self, lines: &[String]) {
+        // single line below the image, the frame above is not scrolled away
+        let summary: Vec<&str> = lines.iter().take(2).map(|line| line.as_str()).collect();
+        print!("{} | {}\x1b[K", summary.join(" | "), self.mode);
+        let _ = io::stdout().flush();
+    }
+}
+
+#[cfg(unix)]
+fn query_window_size() -> Option<(usize, usize)> {
+    // TIOCGWINSZ on whichever standard stream is still attached to the terminal
+    for fd in [libc::STDOUT_FILENO, libc::STDIN_FILENO, libc::STDERR_FILENO] {
+        let mut size = libc::winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: the ioctl only writes into the winsize struct passed by pointer
+        let result = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) };
+        if result == 0 && size.ws_col > 0 && size.ws_row > 0 {
+            return Some((size.ws_col as usize, size.ws_row as usize));
+        }
+    }
+    None
+}
+
+#[cfg(not(unix))]
+fn query_window_size() -> Option<(usize, usize)> {
+    None
+}
+
+fn query_stty_size() -> Option<(usize, usize)> {
+    // prints "rows cols" for the terminal on its stdin
+    let tty = std::fs::File::open("/dev/tty").ok()?;
+    let output = Command::new("stty")
+        .arg("size")
+        .stdin(Stdio::from(tty))
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let text = String::from_utf8(output.stdout).ok()?;
+    let mut parts = text
+        .split_whitespace()
+        .map(|part| part.parse::<usize>().ok());
+    let rows = parts.next()??;
+    let cols = parts.next()??;
+    (rows > 0 && cols > 0).then_some((cols, rows))
+}
+
+fn split_rgb(val: u32) -> (u8, u8, u8) {
+    (
+        ((val >> 16) & 0xFF) as u8,
+        ((val >> 8) & 0xFF) as u8,
+        (val & 0xFF) as u8,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_sized_terminal_renders_one_cell() {
+        let mut terminal = TerminalBackend::new(0, 0, TerminalMode::Ascii);
+        assert_eq!((terminal.cols, terminal.rows), (1, 1));
+        assert_eq!(terminal.pixel_size(8, 4), (1, 1));
+
+        terminal.cols = 0;
+        terminal.rows = 0;
+        assert_eq!(terminal.pixel_size(8, 4), (1, 1));
+        terminal.render_to_string(&[0; 32], 8, 4);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::backends::{Backend, Input, InputKey, InputMouseButton};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Scale, Window, WindowOptions};
+use std::error::Error;
+
+fn map_key(key: Key) -> Option<InputKey> {
+    let input_key = match key {
+        Key::Key0 => InputKey::Char('0'),
+        Key::Key1 => InputKey::Char('1'),
+        Key::Key2 => InputKey::Char('2'),
+        Key::Key3 => InputKey::Char('3'),
+        Key::Key4 => InputKey::Char('4'),
+        Key::Key5 => InputKey::Char('5'),
+        Key::Key6 => InputKey::Char('6'),
+        Key::Key7 => InputKey::Char('7'),
+        Key::Key8 => InputKey::Char('8'),
+        Key::Key9 => InputKey::Char('9'),
+        Key::A => InputKey::Char('a'),
+        Key::B => InputKey::Char('b'),
+        Key::C => InputKey::Char('c'),
+        Key::D => InputKey::Char('d'),
+        Key::E => InputKey::Char('e'),
+        Key::F => InputKey::Char('f'),
+        Key::G => InputKey::Char('g'),
+        Key::H => InputKey::Char('h'),
+        Key::I => InputKey::Char('i'),
+        Key::J => InputKey::Char('j'),
+        Key::K => InputKey::Char('k'),
+        Key::L => InputKey::Char('l'),
+        Key::M => InputKey::Char('m'),
+        Key::N => InputKey::Char('n'),
+        Key::O => InputKey::Char('o'),
+        Key::P => InputKey::Char('p'),
+        Key::Q => InputKey::Char('q'),
+        Key::R => InputKey::Char('r'),
+        Key::S => InputKey::Char('s'),
+        Key::T => InputKey::Char('t'),
+        Key::U => InputKey::Char('u'),
+        Key::V => InputKey::Char('v'),
+        Key::W => InputKey::Char('w'),
+        Key::X => InputKey::Char('x'),
+        Key::Y => InputKey::Char('y'),
+        Key::Z => InputKey::Char('z'),
+        Key::Enter | Key::NumPadEnter => InputKey::Enter,
+        Key::Space => InputKey::Space,
+        Key::Escape => InputKey::Escape,
+        Key::Tab => InputKey::Tab,
+        Key::Backspace => InputKey::Backspace,
+        Key::Up => InputKey::Up,
+        Key::Down => InputKey::Down,
+        Key::Left => InputKey::Left,
+        Key::Right => InputKey::Right,
+        Key::LeftShift => InputKey::LeftShift,
+        Key::RightShift => InputKey::RightShift,
+        Key::LeftCtrl => InputKey::LeftCtrl,
+        Key::RightCtrl => InputKey::RightCtrl,
+        Key::LeftAlt => InputKey::LeftAlt,
+        Key::RightAlt => InputKey::RightAlt,
+        _ => return None,
+    };
+    Some(input_key)
+}
+
+pub struct WindowBackend {
+    pub window: Window,
+}
+
+impl WindowBackend {
+    pub fn new(
+        title: &str,
+        size_x: usize,
+        size_y: usize,
+        scale: Scale,
+    ) -> Result<Self, Box<dyn Error>> {
+        let window = Window::new(
+            title,
+            size_x,
+            size_y,
+            WindowOptions {
+                borderless: false,
+                title: true,
+                scale,
+                resize: false,
+                scale_mode: minifb::ScaleMode::UpperLeft,
+                topmost: true,
+                transparency: false,
+                none: false,
+            },
+        )?;
+        Ok(Self { window })
+    }
+}
+
+impl Backend for WindowBackend {
+    fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    fn poll_input(&mut self) -> Input {
+        let mut input = Input {
+            keys_down: self
+                .window
+                .get_keys()
+                .into_iter()
+                .filter_map(map_key)
+                .collect(),
+            keys_pressed: self
+                .window
+                .get_keys_pressed(KeyRepeat::No)
+                .into_iter()
+                .filter_map(map_key)
+                .collect(),
+            mouse_pos: self.window.get_mouse_pos(MouseMode::Pass),
+            ..Input::default()
+        };
+        if self.window.get_mouse_down(MouseButton::Left) {
+            input.mouse_buttons_down.push(InputMouseButton::Left);
+        }
+        if self.window.get_mouse_down(MouseButton::Right) {
+            input.mouse_buttons_down.push(InputMouseButton::Right);
+        }
+        input
+    }
+
+    fn present(
+        &mut self,
+        buffer: &[u32],
+        size_x: usize,
+        size_y: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        self.window.update_with_buffer(buffer, size_x, size_y)?;
+        Ok(())
+    }
+}
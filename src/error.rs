@@ -0,0 +1,68 @@
+use std::fmt;
+use std::io;
+
+/*
+    Crate-wide error type, returned by constructors and loaders instead of panicking.
+*/
+#[derive(Debug)]
+pub enum RenderError {
+    UnknownColor(String),
+    InvalidResolution(String),
+    InvalidCanvasSize {
+        size_x: usize,
+        size_y: usize,
+        reason: String,
+    },
+    InvalidArgument(String),
+    Parse {
+        source: String,
+        line: usize,
+        reason: String,
+    },
+    Io(io::Error),
+    Image(image::ImageError),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderError::UnknownColor(name) => write!(f, "unknown color \"{}\"", name),
+            RenderError::InvalidResolution(reason) => write!(f, "invalid resolution: {}", reason),
+            RenderError::InvalidCanvasSize {
+                size_x,
+                size_y,
+                reason,
+            } => write!(f, "invalid canvas size {}x{}: {}", size_x, size_y, reason),
+            RenderError::InvalidArgument(reason) => write!(f, "invalid argument: {}", reason),
+            RenderError::Parse {
+                source,
+                line,
+                reason,
+            } => write!(f, "{}:{}: {}", source, line, reason),
+            RenderError::Io(err) => write!(f, "io error: {}", err),
+            RenderError::Image(err) => write!(f, "image error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RenderError::Io(err) => Some(err),
+            RenderError::Image(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RenderError {
+    fn from(err: io::Error) -> Self {
+        RenderError::Io(err)
+    }
+}
+
+impl From<image::ImageError> for RenderError {
+    fn from(err: image::ImageError) -> Self {
+        RenderError::Image(err)
+    }
+}
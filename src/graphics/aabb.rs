@@ -0,0 +1,111 @@
+use crate::vectors::Vector3d;
+use std::fmt;
+
+/*
+    Axis aligned bounding box, min and max corner inclusive.
+    An empty box has min > max, so growing it by any point yields that point.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3d,
+    pub max: Vector3d,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Aabb {
+    pub fn new(min: Vector3d, max: Vector3d) -> Self {
+        Self { min, max }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            min: Vector3d::new(f64::MAX, f64::MAX, f64::MAX),
+            max: Vector3d::new(f64::MIN, f64::MIN, f64::MIN),
+        }
+    }
+
+    pub fn from_points(points: &[Vector3d]) -> Self {
+        let mut aabb = Self::empty();
+        for point in points {
+            aabb.grow(point);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn grow(&mut self, point: &Vector3d) {
+        self.min = Vector3d::new(
+            self.min.x.min(point.x),
+            self.min.y.min(point.y),
+            self.min.z.min(point.z),
+        );
+        self.max = Vector3d::new(
+            self.max.x.max(point.x),
+            self.max.y.max(point.y),
+            self.max.z.max(point.z),
+        );
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut aabb = *self;
+        if !other.is_empty() {
+            aabb.grow(&other.min);
+            aabb.grow(&other.max);
+        }
+        aabb
+    }
+
+    pub fn center(&self) -> Vector3d {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extent(&self) -> Vector3d {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let e = self.extent();
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
+    pub fn largest_axis(&self) -> usize {
+        let e = self.extent();
+        if e.x >= e.y && e.x >= e.z {
+            0
+        } else if e.y >= e.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn bounding_sphere(&self) -> (Vector3d, f64) {
+        // center and radius of the sphere through all 8 corners
+        (self.center(), self.extent().length() * 0.5)
+    }
+}
+
+pub fn axis_value(vec: &Vector3d, axis: usize) -> f64 {
+    match axis {
+        0 => vec.x,
+        1 => vec.y,
+        _ => vec.z,
+    }
+}
+
+impl fmt::Display for Aabb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Aabb [{} .. {}]", self.min, self.max)
+    }
+}
@@ -0,0 +1,327 @@
+use crate::error::RenderError;
+use crate::graphics::canvas::Canvas;
+use crate::graphics::colors::linear_to_srgb;
+use crate::vectors::Vector4d;
+use image::{ImageBuffer, ImageFormat, Rgba, Rgba32FImage, RgbaImage};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/*
+    Auxiliary render passes (arbitrary output variables) for compositing and datasets.
+
+    Every pass is read from the supersized buffers of the last frame, at the supersized pixel
+    in the center of each output pixel. So IDs and normals are never mixed across edges and all
+    passes line up with each other, but they are not antialiased like the beauty image.
+    Float formats (PFM, OpenEXR) keep the raw values, integer formats (8 and 16 bit PNG) store
+    them remapped to [0, 1]. Meshes carry no texture coordinates, so there is no UV pass.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Aov {
+    Depth,    // linear along the view axis, normalized to the visible range in integer formats
+    Normal,   // camera space, remapped from [-1, 1] in integer formats
+    Albedo,   // unlit linear surface color, sRGB encoded in integer formats, needs the G-buffer
+    ObjectId, // mesh index + 1 (and face index + 1 in float formats), 0 for background
+}
+
+impl Aov {
+    pub const ALL: [Aov; 4] = [Aov::Depth, Aov::Normal, Aov::Albedo, Aov::ObjectId];
+
+    pub fn from_name(name: &str) -> Result<Self, RenderError> {
+        match name.trim().to_lowercase().as_str() {
+            "depth" => Ok(Aov::Depth),
+            "normal" => Ok(Aov::Normal),
+            "albedo" => Ok(Aov::Albedo),
+            "id" => Ok(Aov::ObjectId),
+            _ => Err(RenderError::InvalidArgument(format!(
+                "unknown render pass \"{}\"",
+                name
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Aov {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Aov::Depth => write!(f, "depth"),
+            Aov::Normal => write!(f, "normal"),
+            Aov::Albedo => write!(f, "albedo"),
+            Aov::ObjectId => write!(f, "id"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AovFormat {
+    Png8,
+    Png16,
+    Pfm, // portable float map, rgb only
+    Exr, // OpenEXR, 32 bit float rgba
+}
+
+impl AovFormat {
+    pub fn from_name(name: &str) -> Result<Self, RenderError> {
+        match name.trim().to_lowercase().as_str() {
+            "png" | "png8" => Ok(AovFormat::Png8),
+            "png16" => Ok(AovFormat::Png16),
+            "pfm" => Ok(AovFormat::Pfm),
+            "exr" => Ok(AovFormat::Exr),
+            _ => Err(RenderError::InvalidArgument(format!(
+                "unknown pass format \"{}\", expected png, png16, pfm or exr",
+                name
+            ))),
+        }
+    }
+
+    pub fn from_path(path: &str) -> Result<Self, RenderError> {
+        // by file extension, PNGs get 8 bits
+        let extension = path.rsplit_once('.').map_or("", |(_, extension)| extension);
+        Self::from_name(extension)
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, AovFormat::Pfm | AovFormat::Exr)
+    }
+
+    fn max_value(&self) -> f64 {
+        // largest stored integer, 1.0 for float formats
+        match self {
+            AovFormat::Png8 => u8::MAX as f64,
+            AovFormat::Png16 => u16::MAX as f64,
+            AovFormat::Pfm | AovFormat::Exr => 1.0,
+        }
+    }
+}
+
+impl fmt::Display for AovFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AovFormat::Png8 => write!(f, "8 bit PNG"),
+            AovFormat::Png16 => write!(f, "16 bit PNG"),
+            AovFormat::Pfm => write!(f, "PFM"),
+            AovFormat::Exr => write!(f, "OpenEXR"),
+        }
+    }
+}
+
+pub fn calc_aov(
+    canvas: &Canvas,
+    aov: Aov,
+    format: AovFormat,
+) -> Result<Vec<Vector4d>, RenderError> {
+    /*
+        the pass per output pixel as rgba, rows top first, encoded as the format stores it.
+        alpha is 1.0 where a surface was drawn and 0.0 for background
+    */
+    let centers: Vec<usize> = (0..canvas.size_y)
+        .flat_map(|y| (0..canvas.size_x).map(move |x| (x, y)))
+        .map(|(x, y)| canvas.supersized_center_index(x, y))
+        .collect();
+
+    let pixels = match aov {
+        Aov::Depth => {
+            // projected z is linear in camera space z, the camera looks down -z
+            let p = canvas.camera.calc_perspective_projection_matrix();
+            let depths: Vec<Option<f64>> = centers
+                .iter()
+                .map(|&idx| {
+                    let z = canvas.z_buffer_supersized[idx];
+                    (z != f64::MAX).then(|| -(z - p.d.u) / p.d.z)
+                })
+                .collect();
+            if format.is_float() {
+                // background is infinitely far away
+                depths
+                    .iter()
+                    .map(|depth| match depth {
+                        Some(depth) => Vector4d::new(*depth, *depth, *depth, 1.0),
+                        None => Vector4d::new(f64::INFINITY, f64::INFINITY, f64::INFINITY, 0.0),
+                    })
+                    .collect()
+            } else {
+                // nearest visible surface black, farthest and background white
+                let near = depths.iter().flatten().fold(f64::MAX, |a, b| a.min(*b));
+                let far = depths.iter().flatten().fold(f64::MIN, |a, b| a.max(*b));
+                let range = (far - near).max(f64::EPSILON);
+                depths
+                    .iter()
+                    .map(|depth| match depth {
+                        Some(depth) => {
+                            let depth = (depth - near) / range;
+                            Vector4d::new(depth, depth, depth, 1.0)
+                        }
+                        None => Vector4d::new(1.0, 1.0, 1.0, 0.0),
+                    })
+                    .collect()
+            }
+        }
+        Aov::Normal => centers
+            .iter()
+            .map(|&idx| {
+                let normal = canvas.normal_buffer_supersized[idx];
+                if normal.length() == 0.0 {
+                    return Vector4d::zeros();
+                }
+                let normal = normal.normalize();
+                if format.is_float() {
+                    Vector4d::from_vector3d(&normal, 1.0)
+                } else {
+                    Vector4d::new(
+                        normal.x * 0.5 + 0.5,
+                        normal.y * 0.5 + 0.5,
+                        normal.z * 0.5 + 0.5,
+                        1.0,
+                    )
+                }
+            })
+            .collect(),
+        Aov::Albedo => {
+            let g_buffer = canvas.g_buffer_supersized.as_ref().ok_or_else(|| {
+                RenderError::InvalidArgument(
+                    "the albedo pass needs the G-buffer, see Canvas::set_aovs_enabled".to_string(),
+                )
+            })?;
+            centers
+                .iter()
+                .map(|&idx| {
+                    if !g_buffer.covered[idx] {
+                        return Vector4d::zeros();
+                    }
+                    let albedo = g_buffer.albedo[idx];
+                    if format.is_float() || !canvas.srgb {
+                        Vector4d::new(albedo.x, albedo.y, albedo.z, 1.0)
+                    } else {
+                        Vector4d::new(
+                            linear_to_srgb(albedo.x),
+                            linear_to_srgb(albedo.y),
+                            linear_to_srgb(albedo.z),
+                            1.0,
+                        )
+                    }
+                })
+                .collect()
+        }
+        Aov::ObjectId => {
+            let id_buffer = canvas.id_buffer_supersized.as_ref().ok_or_else(|| {
+                RenderError::InvalidArgument(
+                    "the ID pass needs the ID buffer to be enabled".to_string(),
+                )
+            })?;
+            let max_value = format.max_value();
+            if !format.is_float() && canvas.scene.meshes().len() as f64 > max_value {
+                return Err(RenderError::InvalidArgument(format!(
+                    "{} meshes do not fit into a {} ID pass",
+                    canvas.scene.meshes().len(),
+                    format
+                )));
+            }
+            centers
+                .iter()
+                .map(|&idx| match id_buffer[idx] {
+                    Some(id) if format.is_float() => {
+                        Vector4d::new((id.mesh + 1) as f64, (id.face + 1) as f64, 0.0, 1.0)
+                    }
+                    Some(id) => {
+                        // the stored integer is the mesh index + 1 itself
+                        let value = (id.mesh + 1) as f64 / max_value;
+                        Vector4d::new(value, value, value, 1.0)
+                    }
+                    None => Vector4d::zeros(),
+                })
+                .collect()
+        }
+    };
+    Ok(pixels)
+}
+
+pub fn save_aov_image(
+    pixels: &[Vector4d],
+    size_x: usize,
+    size_y: usize,
+    format: AovFormat,
+    path: &str,
+) -> Result<(), RenderError> {
+    /*
+        writes a pass as returned by calc_aov, in the given format regardless of the
+        file extension
+    */
+    if pixels.len() != size_x * size_y {
+        return Err(RenderError::InvalidArgument(format!(
+            "pass has {} pixels, expected {}x{}",
+            pixels.len(),
+            size_x,
+            size_y
+        )));
+    }
+    let max_value = format.max_value();
+    let quantize = |c: f64| (c.clamp(0.0, 1.0) * max_value).round();
+    let pixel_at = |x: u32, y: u32| pixels[y as usize * size_x + x as usize];
+
+    match format {
+        AovFormat::Png8 => {
+            let image = RgbaImage::from_fn(size_x as u32, size_y as u32, |x, y| {
+                let c = pixel_at(x, y);
+                Rgba([c.x, c.y, c.z, c.u].map(|c| quantize(c) as u8))
+            });
+            image.save_with_format(path, ImageFormat::Png)?;
+        }
+        AovFormat::Png16 => {
+            let image: ImageBuffer<Rgba<u16>, Vec<u16>> =
+                ImageBuffer::from_fn(size_x as u32, size_y as u32, |x, y| {
+                    let c = pixel_at(x, y);
+                    Rgba([c.x, c.y, c.z, c.u].map(|c| quantize(c) as u16))
+                });
+            image.save_with_format(path, ImageFormat::Png)?;
+        }
+        AovFormat::Exr => {
+            let image = Rgba32FImage::from_fn(size_x as u32, size_y as u32, |x, y| {
+                let c = pixel_at(x, y);
+                Rgba([c.x, c.y, c.z, c.u].map(|c| c as f32))
+            });
+            image.save_with_format(path, ImageFormat::OpenExr)?;
+        }
+        AovFormat::Pfm => {
+            // text header, then little endian floats with the bottom row first
+            let mut file = BufWriter::new(File::create(path)?);
+            write!(file, "PF\n{} {}\n-1.0\n", size_x, size_y)?;
+            for y in (0..size_y).rev() {
+                for c in &pixels[y * size_x..(y + 1) * size_x] {
+                    for c in [c.x, c.y, c.z] {
+                        file.write_all(&(c as f32).to_le_bytes())?;
+                    }
+                }
+            }
+            file.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_from_path_uses_the_extension() {
+        assert_eq!(AovFormat::from_path("depth.png").unwrap(), AovFormat::Png8);
+        assert_eq!(
+            AovFormat::from_path("out/normal.PFM").unwrap(),
+            AovFormat::Pfm
+        );
+        assert_eq!(
+            AovFormat::from_path("passes.v2/id.exr").unwrap(),
+            AovFormat::Exr
+        );
+        for path in ["albedo", "./albedo", "passes.v2/albedo", "albedo.jpg"] {
+            assert!(
+                matches!(
+                    AovFormat::from_path(path),
+                    Err(RenderError::InvalidArgument(_))
+                ),
+                "{}",
+                path
+            );
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use crate::graphics::aabb::{Aabb, axis_value};
+use crate::graphics::ray::{Ray, RayHit};
+use crate::graphics::shapes::Mesh;
+
+/*
+    Bounding volume hierarchy over all triangles of a list of meshes (usually Scene::meshes),
+    in world space.
+
+    Built top down with a binned surface area heuristic (SAH). Nodes are stored
+    in one flat list, the root is nodes[0]. Leaves reference a contiguous range
+    of `primitives`, inner nodes their two children.
+
+    After mesh transforms change, `refit` recomputes all bounds while keeping the
+    tree topology. That is cheap but gets slower to traverse for large movements,
+    `build` starts from scratch.
+*/
+
+const SAH_BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: f64 = 1.0;
+const INTERSECTION_COST: f64 = 1.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BvhPrimitive {
+    pub mesh: usize,
+    pub face: usize,
+    pub bounds: Aabb,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BvhNode {
+    pub bounds: Aabb,
+    // inner node: indices of both children, leaf: range into primitives
+    pub left: usize,
+    pub right: usize,
+    pub first: usize,
+    pub count: usize,
+}
+
+impl BvhNode {
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    pub primitives: Vec<BvhPrimitive>,
+}
+
+impl Bvh {
+    pub fn build(meshes: &[Mesh]) -> Bvh {
+        let mut primitives = vec![];
+        for (mesh_idx, mesh) in meshes.iter().enumerate() {
+            let world_vertices = mesh.world_vertices();
+            for (face_idx, face) in mesh.faces.iter().enumerate() {
+                primitives.push(BvhPrimitive {
+                    mesh: mesh_idx,
+                    face: face_idx,
+                    bounds: Aabb::from_points(&[
+                        world_vertices[face[0]],
+                        world_vertices[face[1]],
+                        world_vertices[face[2]],
+                    ]),
+                });
+            }
+        }
+
+        let mut bvh = Bvh {
+            nodes: vec![],
+            primitives,
+        };
+        if !bvh.primitives.is_empty() {
+            bvh.build_node(0, bvh.primitives.len());
+        }
+        bvh
+    }
+
+    fn build_node(&mut self, first: usize, count: usize) -> usize {
+        let primitives = &self.primitives[first..first + count];
+        let mut bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for primitive in primitives {
+            bounds = bounds.union(&primitive.bounds);
+            centroid_bounds.grow(&primitive.bounds.center());
+        }
+
+        let node_idx = self.nodes.len();
+        self.nodes.push(BvhNode {
+            bounds,
+            left: 0,
+            right: 0,
+            first,
+            count,
+        });
+
+        if count <= MAX_LEAF_SIZE {
+            return node_idx;
+        }
+
+        let Some((axis, split)) = Self::find_sah_split(primitives, &bounds, &centroid_bounds)
+        else {
+            return node_idx;
+        };
+
+        // partition primitives around the split plane
+        self.primitives[first..first + count].sort_by(|a, b| {
+            axis_value(&a.bounds.center(), axis).total_cmp(&axis_value(&b.bounds.center(), axis))
+        });
+        let left_count = self.primitives[first..first + count]
+            .iter()
+            .take_while(|primitive| axis_value(&primitive.bounds.center(), axis) < split)
+            .count();
+        if left_count == 0 || left_count == count {
+            return node_idx;
+        }
+
+        let left = self.build_node(first, left_count);
+        let right = self.build_node(first + left_count, count - left_count);
+        self.nodes[node_idx].left = left;
+        self.nodes[node_idx].right = right;
+        self.nodes[node_idx].count = 0;
+        node_idx
+    }
+
+    fn find_sah_split(
+        primitives: &[BvhPrimitive],
+        bounds: &Aabb,
+        centroid_bounds: &Aabb,
+    ) -> Option<(usize, f64)> {
+        /*
+            returns split axis and position if splitting is cheaper than a leaf
+        */
+        let axis = centroid_bounds.largest_axis();
+        let c_min = axis_value(&centroid_bounds.min, axis);
+        let c_max = axis_value(&centroid_bounds.max, axis);
+        if c_max - c_min <= f64::EPSILON {
+            return None;
+        }
+
+        let mut bin_bounds = [Aabb::empty(); SAH_BINS];
+        let mut bin_counts = [0usize; SAH_BINS];
+        let bin_of = |center: f64| -> usize {
+            (((center - c_min) / (c_max - c_min)) * SAH_BINS as f64).min(SAH_BINS as f64 - 1.0)
+                as usize
+        };
+        for primitive in primitives {
+            let bin = bin_of(axis_value(&primitive.bounds.center(), axis));
+            bin_bounds[bin] = bin_bounds[bin].union(&primitive.bounds);
+            bin_counts[bin] += 1;
+        }
+
+        let parent_area = bounds.surface_area().max(f64::EPSILON);
+        let mut best: Option<(f64, usize)> = None;
+        for split_bin in 1..SAH_BINS {
+            let (mut left, mut right) = (Aabb::empty(), Aabb::empty());
+            let (mut left_count, mut right_count) = (0, 0);
+            for bin in 0..SAH_BINS {
+                if bin < split_bin {
+                    left = left.union(&bin_bounds[bin]);
+                    left_count += bin_counts[bin];
+                } else {
+                    right = right.union(&bin_bounds[bin]);
+                    right_count += bin_counts[bin];
+                }
+            }
+            if left_count == 0 || right_count == 0 {
+                continue;
+            }
+            let cost = TRAVERSAL_COST
+                + INTERSECTION_COST
+                    * (left.surface_area() * left_count as f64
+                        + right.surface_area() * right_count as f64)
+                    / parent_area;
+            if best.is_none_or(|(best_cost, _)| cost < best_cost) {
+                best = Some((cost, split_bin));
+            }
+        }
+
+        let (best_cost, split_bin) = best?;
+        if best_cost >= INTERSECTION_COST * primitives.len() as f64 {
+            return None;
+        }
+        Some((
+            axis,
+            c_min + (c_max - c_min) * split_bin as f64 / SAH_BINS as f64,
+        ))
+    }
+
+    pub fn refit(&mut self, meshes: &[Mesh]) {
+        /*
+            recomputes primitive and node bounds for the current mesh transforms,
+            expects the same meshes and faces the tree was built with
+        */
+        let world_vertices: Vec<_> = meshes.iter().map(|mesh| mesh.world_vertices()).collect();
+        for primitive in self.primitives.iter_mut() {
+            let face = &meshes[primitive.mesh].faces[primitive.face];
+            let vertices = &world_vertices[primitive.mesh];
+            primitive.bounds =
+                Aabb::from_points(&[vertices[face[0]], vertices[face[1]], vertices[face[2]]]);
+        }
+
+        // children are always pushed after their parent, so walk backwards
+        for node_idx in (0..self.nodes.len()).rev() {
+            let node = self.nodes[node_idx];
+            self.nodes[node_idx].bounds = if node.is_leaf() {
+                self.primitives[node.first..node.first + node.count]
+                    .iter()
+                    .fold(Aabb::empty(), |bounds, primitive| {
+                        bounds.union(&primitive.bounds)
+                    })
+            } else {
+                self.nodes[node.left]
+                    .bounds
+                    .union(&self.nodes[node.right].bounds)
+            };
+        }
+    }
+
+    pub fn traverse(
+        &self,
+        mut visit_node: impl FnMut(&Aabb) -> bool,
+        mut visit_primitive: impl FnMut(&BvhPrimitive),
+    ) {
+        /*
+            depth first traversal, children of a node are only visited if
+            visit_node returns true for its bounds
+        */
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![0];
+        while let Some(node_idx) = stack.pop() {
+            let node = &self.nodes[node_idx];
+            if !visit_node(&node.bounds) {
+                continue;
+            }
+            if node.is_leaf() {
+                for primitive in &self.primitives[node.first..node.first + node.count] {
+                    visit_primitive(primitive);
+                }
+            } else {
+                stack.push(node.right);
+                stack.push(node.left);
+            }
+        }
+    }
+
+    pub fn intersect_ray(&self, ray: &Ray, meshes: &[Mesh]) -> Option<RayHit> {
+        /*
+            closest hit of the ray with any triangle, nodes farther away than the
+            closest hit so far are skipped
+        */
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut closest: Option<RayHit> = None;
+        let mut stack = vec![0];
+        while let Some(node_idx) = stack.pop() {
+            let node = &self.nodes[node_idx];
+            let Some((t_enter, _)) = ray.intersect_aabb(&node.bounds) else {
+                continue;
+            };
+            if closest.is_some_and(|hit| hit.t < t_enter) {
+                continue;
+            }
+            if !node.is_leaf() {
+                stack.push(node.right);
+                stack.push(node.left);
+                continue;
+            }
+
+            for primitive in &self.primitives[node.first..node.first + node.count] {
+                let mesh = &meshes[primitive.mesh];
+                let face = &mesh.faces[primitive.face];
+                let (p1, p2, p3) = (
+                    mesh.world_vertex(face[0]),
+                    mesh.world_vertex(face[1]),
+                    mesh.world_vertex(face[2]),
+                );
+                let Some((t, u, v)) = ray.intersect_triangle(&p1, &p2, &p3) else {
+                    continue;
+                };
+                if closest.is_some_and(|hit| hit.t <= t) {
+                    continue;
+                }
+
+                // smooth normal if the mesh has vertex normals, face normal otherwise
+                let mut normal = if mesh.vertex_normals.len() == mesh.vertices.len() {
+                    mesh.world_normal(
+                        &(mesh.vertex_normals[face[0]] * (1.0 - u - v)
+                            + mesh.vertex_normals[face[1]] * u
+                            + mesh.vertex_normals[face[2]] * v),
+                    )
+                } else {
+                    (p2 - p1).cross(p3 - p1).normalize()
+                };
+                if normal.dot(ray.direction) > 0.0 {
+                    normal *= -1.0;
+                }
+                let front_face = (p2 - p1).cross(p3 - p1).dot(ray.direction) < 0.0;
+
+                closest = Some(RayHit {
+                    mesh: primitive.mesh,
+                    face: primitive.face,
+                    t,
+                    point: ray.at(t),
+                    normal,
+                    front_face,
+                    u,
+                    v,
+                });
+            }
+        }
+        closest
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::empty(), |root| root.bounds)
+    }
+}
@@ -1,7 +1,8 @@
+use crate::graphics::frustum::Frustum;
+use crate::graphics::ray::Ray;
 use crate::util::calc_perspective_matrix;
-use crate::vectors::Vector3d;
 use crate::vectors::matrices::Matrix4x4;
-use minifb::MouseMode;
+use crate::vectors::{Vector3d, Vector4d};
 
 #[derive(Debug, Clone)]
 pub struct Camera {
@@ -56,4 +57,59 @@ impl Camera {
 
         return (g, u, v, w);
     }
+
+    pub fn calc_camera_matrix(&self) -> Matrix4x4 {
+        // world space -> camera space
+        let (_, u, v, w) = self.calc_guvw();
+        Matrix4x4::from_vecs(
+            Vector4d::from_vector3d(&u, -u.dot(self.e)),
+            Vector4d::from_vector3d(&v, -v.dot(self.e)),
+            Vector4d::from_vector3d(&w, -w.dot(self.e)),
+            Vector4d::new(0.0, 0.0, 0.0, 1.0),
+        )
+    }
+
+    pub fn calc_ray(&self, ndc_x: f64, ndc_y: f64) -> Ray {
+        /*
+            world space ray through a point in normalized device coordinates [-1, 1].
+
+            Inverts calc_perspective_projection_matrix: for a fixed ndc position, x and y in
+            camera space are linear in z, with w = z * (-n / (f - n)) - f * n / (f - n).
+            The ray starts at the projection's apex (w = 0) and runs towards w > 0.
+        */
+        let (_, u, v, w) = self.calc_guvw();
+        let p = self.calc_perspective_projection_matrix();
+
+        let cam_point = |z: f64| -> Vector3d {
+            let w_clip = p.d.z * z + p.d.u;
+            Vector3d::new(
+                (ndc_x * w_clip - p.a.z * z) / p.a.x,
+                (ndc_y * w_clip - p.b.z * z) / p.b.y,
+                z,
+            )
+        };
+        let to_world =
+            |point: Vector3d| -> Vector3d { self.e + u * point.x + v * point.y + w * point.z };
+
+        let z_apex = -p.d.u / p.d.z;
+        let origin = to_world(cam_point(z_apex));
+        let target = to_world(cam_point(z_apex - self.f.max(1.0)));
+        Ray::new(origin, target - origin)
+    }
+
+    pub fn calc_pixel_ray(&self, px: f64, py: f64, size_x: usize, size_y: usize) -> Ray {
+        // ray through the center of pixel (px, py), y pointing down as in window coordinates
+        let ndc_x = (px + 0.5) / size_x as f64 * 2.0 - 1.0;
+        let ndc_y = 1.0 - (py + 0.5) / size_y as f64 * 2.0;
+        self.calc_ray(ndc_x, ndc_y)
+    }
+
+    pub fn calc_frustum(&self) -> Frustum {
+        // world space frustum planes
+        Frustum::from_matrix(
+            &self
+                .calc_perspective_projection_matrix()
+                .times_matrix(&self.calc_camera_matrix()),
+        )
+    }
 }
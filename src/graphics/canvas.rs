@@ -1,12 +1,34 @@
-use crate::graphics::colors::{color_vec_from_f64, color_vec_from_u32, color_vec_to_u32};
-use crate::graphics::fragment_shader::phong_frag;
+use crate::error::RenderError;
+use crate::graphics::aov::{Aov, AovFormat, calc_aov, save_aov_image};
+use crate::graphics::colors::{
+    color_vec_from_f64, color_vec_from_u32, color_vec_linear_to_srgb, color_vec_srgb_to_linear,
+    color_vec_to_u32, premultiply, unpremultiply,
+};
+use crate::graphics::compositing::{BlendMode, composite_pixel};
+use crate::graphics::fragment_shader::{AMBIENT, ShadingMode, shade_frag};
+use crate::graphics::fxaa::Fxaa;
+use crate::graphics::gbuffer::{GBuffer, Surface};
+use crate::graphics::msaa::{Msaa, TrianglePlanes, signed_area};
+use crate::graphics::object_id::{ObjectId, detect_mesh_edges, save_mask};
+use crate::graphics::outline::Outline;
+use crate::graphics::path_tracer::PathTracer;
+use crate::graphics::post_effects::{PostEffect, PostEffectChain};
+use crate::graphics::ray::RayHit;
+use crate::graphics::ray_tracer::RayTracer;
+use crate::graphics::resampling::{ReconstructionFilter, UpscaleFilter, resample};
 use crate::graphics::scanline::{ActiveEdgeTable, ActiveEdgeTableEntry, EdgeTable, EdgeTableEntry};
+use crate::graphics::sdf::{SdfShape, sphere_trace};
 use crate::graphics::shapes::{Mesh, Scene};
+use crate::graphics::ssao::Ssao;
+use crate::graphics::tone_mapping::ToneMapping;
+use crate::graphics::transparency::{TransparentFragment, resolve_transparent_fragments};
 use crate::graphics::{Camera, PointLight, Triangle3d, alpha_blend};
 use crate::vectors::matrices::Matrix4x4;
 use crate::vectors::{IntegerVector2d, Vector3d, Vector4d};
 use core::f64;
 use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub enum SSAA {
@@ -16,6 +38,7 @@ pub enum SSAA {
     X4,
     X16,
     X64,
+    Factor(f64), // any scale per axis, e.g. 1.5 renders 2.25 samples per pixel, below 1.0 upscales
 }
 
 impl fmt::Display for SSAA {
@@ -28,18 +51,108 @@ impl fmt::Display for SSAA {
             SSAA::X4 => write!(f, "4X SSAA"),
             SSAA::X16 => write!(f, "16X SSAA"),
             SSAA::X64 => write!(f, "64X SSAA"),
+            SSAA::Factor(fac) if *fac < 1.0 => {
+                write!(f, "{}X SSAA (Upscaling mode)", fac * fac)
+            }
+            SSAA::Factor(fac) => write!(f, "{}X SSAA", fac * fac),
+        }
+    }
+}
+
+impl SSAA {
+    // presets the viewer steps through, ordered by factor
+    const PRESETS: [SSAA; 6] = [
+        SSAA::X0_125,
+        SSAA::X0_25,
+        SSAA::X1,
+        SSAA::X4,
+        SSAA::X16,
+        SSAA::X64,
+    ];
+
+    pub fn fac(&self) -> f64 {
+        // scale of the supersized buffer per axis
+        match self {
+            SSAA::X0_125 => 0.25,
+            SSAA::X0_25 => 0.5,
+            SSAA::X1 => 1.0,
+            SSAA::X4 => 2.0,
+            SSAA::X16 => 4.0,
+            SSAA::X64 => 8.0,
+            SSAA::Factor(fac) => *fac,
         }
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Renderer {
+    Rasterizer,
+    RayTracer(RayTracer),
+    PathTracer(PathTracer),
+}
+
+impl fmt::Display for Renderer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Renderer::Rasterizer => write!(f, "Rasterizer"),
+            Renderer::RayTracer(ray_tracer) => write!(f, "{}", ray_tracer),
+            Renderer::PathTracer(path_tracer) => write!(f, "{}", path_tracer),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    pub meshes_drawn: usize,
+    pub meshes_culled: usize,
+
+    pub triangles_total: usize,
+    pub triangles_drawn: usize,
+    pub triangles_frustum_culled: usize,
+    pub triangles_backface_culled: usize,
+    pub triangles_clipped: usize, // partially out of bounds, not drawn
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "meshes: {} drawn, {} culled | triangles: {} of {} drawn, {} frustum culled, {} backface culled, {} clipped",
+            self.meshes_drawn,
+            self.meshes_culled,
+            self.triangles_drawn,
+            self.triangles_total,
+            self.triangles_frustum_culled,
+            self.triangles_backface_culled,
+            self.triangles_clipped
+        )
+    }
+}
+
+// ssaa_fac, supersized sizes and halves, and freshly allocated supersized buffers
+pub type SsaaVariables = (
+    f64,
+    usize,
+    usize,
+    usize,
+    usize,
+    Vec<Vector4d>,
+    Vec<f64>,
+    Vec<Vector3d>,
+);
+
 #[derive(Clone)]
 pub struct Canvas {
     pub size_x: usize,
     pub size_y: usize,
 
     pub buffer: Vec<u32>,
-    pub buffer_supersized: Vec<u32>,
+    pub buffer_supersized: Vec<Vector4d>, // linear HDR rgba, premultiplied, resolved by apply_ssaa
     pub z_buffer_supersized: Vec<f64>,
+    pub normal_buffer_supersized: Vec<Vector3d>,
+    pub id_buffer_supersized: Option<Vec<Option<ObjectId>>>, // only allocated if enabled
+    pub g_buffer_supersized: Option<GBuffer>, // allocated for deferred shading and SSAO
+    pub transparent_fragments: Vec<TransparentFragment>, // A-buffer, blended by resolve_transparency
     pub bg_color: Vector4d,
     pub lights: Vec<PointLight>,
 
@@ -51,11 +164,63 @@ pub struct Canvas {
     pub size_y_supersized: usize,
     pub size_x_supersized_half: usize,
     pub size_y_supersized_half: usize,
+    pub ssaa_filter: ReconstructionFilter, // downsampling in apply_ssaa
+    pub upscale_filter: UpscaleFilter,     // upscaling mode, factors below 1.0
+
+    // screen-space ambient occlusion of rasterized frames
+    pub ssao: Option<Ssao>,
+
+    // rasterize into the G-buffer first and light every visible pixel once afterwards,
+    // translucent meshes, implicit shapes and MSAA stay forward shaded
+    pub deferred: bool,
+
+    // keep the G-buffer for the albedo render pass even if nothing else needs it
+    pub aovs_enabled: bool,
+
+    // post-process edge anti-aliasing on the resolved buffer, alongside or instead of SSAA
+    pub fxaa: Option<Fxaa>,
+
+    // multisampling in the rasterizer, per sample of each supersized pixel
+    pub msaa: Option<Msaa>,
+    pub msaa_color_samples: Vec<Vector4d>,
+    pub msaa_depth_samples: Vec<f64>,
+    // per supersized pixel, whether its samples were written this frame. untouched samples
+    // count as background at infinite depth, so the sample buffers never have to be cleared
+    pub msaa_touched: Vec<bool>,
 
     // scene, this holds all meshes to be rendered
     pub scene: Scene,
     pub render_smooth: bool,
     pub camera: Camera,
+
+    // applied when resolving the HDR buffer, exposure is a plain factor
+    pub tone_mapping: ToneMapping,
+    pub exposure: f64,
+
+    // decode input colors and encode the output, off means shading on the raw sRGB values
+    pub srgb: bool,
+    pub renderer: Renderer,
+
+    // progressive path tracing, sums of all samples per supersized pixel so far
+    pub accumulation_buffer_supersized: Vec<Vector3d>,
+    pub accumulated_samples: usize,
+    pub accumulation_time: Duration,
+
+    // cel shading and outlines
+    pub shading_mode: ShadingMode,
+    pub outline: Option<Outline>,
+
+    // applied in order after apply_ssaa, skipped while disabled
+    pub post_effects: PostEffectChain,
+    pub post_effects_enabled: bool,
+
+    // statistics of the last render_scene_to_buffer call
+    pub stats: RenderStats,
+
+    // picking, the selected mesh is tinted with highlight_color
+    pub selected_mesh: Option<usize>,
+    pub highlight_color: Vector4d,
+    pub selection_outline: Option<Outline>, // drawn around the selected mesh, needs the ID buffer
 }
 
 impl Canvas {
@@ -66,7 +231,7 @@ impl Canvas {
         ssaa: SSAA,
         render_smooth: bool,
         camera: Camera,
-    ) -> Canvas {
+    ) -> Result<Canvas, RenderError> {
         let (
             ssaa_fac,
             size_x_supersized,
@@ -75,8 +240,14 @@ impl Canvas {
             size_y_supersized_half,
             buffer_supersized,
             z_buffer_supersized,
-        ) = Self::calc_ssaa_variables(&ssaa, size_x, size_y, &bg_color);
-        Canvas {
+            normal_buffer_supersized,
+        ) = Self::calc_ssaa_variables(
+            &ssaa,
+            size_x,
+            size_y,
+            &premultiply(&color_vec_srgb_to_linear(&bg_color)),
+        )?;
+        Ok(Canvas {
             size_x,
             size_y,
 
@@ -90,13 +261,47 @@ impl Canvas {
             size_y_supersized,
             size_x_supersized_half,
             size_y_supersized_half,
+            ssaa_filter: ReconstructionFilter::Box,
+            upscale_filter: UpscaleFilter::Bilinear,
+            ssao: None,
+            deferred: false,
+            aovs_enabled: false,
+            fxaa: None,
+            msaa: None,
+            msaa_color_samples: vec![],
+            msaa_depth_samples: vec![],
+            msaa_touched: vec![],
 
             camera: camera,
             z_buffer_supersized,
+            normal_buffer_supersized,
+            id_buffer_supersized: None,
+            g_buffer_supersized: None,
+            transparent_fragments: vec![],
             buffer_supersized,
             scene: Scene::new(),
             render_smooth,
-        }
+            tone_mapping: ToneMapping::Clamp,
+            exposure: 1.0,
+            srgb: true,
+            renderer: Renderer::Rasterizer,
+
+            accumulation_buffer_supersized: vec![],
+            accumulated_samples: 0,
+            accumulation_time: Duration::ZERO,
+
+            shading_mode: ShadingMode::Phong,
+            outline: None,
+
+            post_effects: vec![],
+            post_effects_enabled: true,
+
+            stats: RenderStats::default(),
+
+            selected_mesh: None,
+            highlight_color: Vector4d::new(1.0, 0.5, 0.0, 1.0),
+            selection_outline: None,
+        })
     }
 
     pub fn calc_ssaa_variables(
@@ -104,46 +309,35 @@ impl Canvas {
         size_x: usize,
         size_y: usize,
         bg_color: &Vector4d,
-    ) -> (f64, usize, usize, usize, usize, Vec<u32>, Vec<f64>) {
-        let ssaa_fac;
-        match ssaa {
-            SSAA::X0_125 => ssaa_fac = 0.25,
-            SSAA::X0_25 => ssaa_fac = 0.5,
-            SSAA::X1 => ssaa_fac = 1.0,
-            SSAA::X4 => ssaa_fac = 2.0,
-            SSAA::X16 => ssaa_fac = 4.0,
-            SSAA::X64 => ssaa_fac = 8.0,
-        }
-
-        // check if upscaling is possible
-        if ssaa_fac == 0.25 {
-            if size_x % 2 != 0 || size_y % 2 != 0 {
-                panic!(
-                    "Canvas size must be divisible by 2 for 0.25 upscaling mode, found {}, {}",
-                    size_x, size_y
-                )
-            }
-        } else if ssaa_fac == 0.125 {
-            if size_x % 4 != 0 || size_y % 4 != 0 {
-                panic!(
-                    "Canvas size must be divisible by 4 for 0.125 upscaling mode, found {}, {}",
-                    size_x, size_y
-                )
-            }
+    ) -> Result<SsaaVariables, RenderError> {
+        let ssaa_fac = ssaa.fac();
+        if !ssaa_fac.is_finite() || ssaa_fac <= 0.0 {
+            return Err(RenderError::InvalidArgument(format!(
+                "SSAA factor must be positive, got {}",
+                ssaa_fac
+            )));
         }
 
-        let size_x_supersized = size_x as f64 * ssaa_fac;
-        let size_y_supersized = size_y as f64 * ssaa_fac;
+        if size_x == 0 || size_y == 0 {
+            return Err(RenderError::InvalidCanvasSize {
+                size_x,
+                size_y,
+                reason: "canvas must not be empty".to_string(),
+            });
+        }
+
+        // fractional factors round to whole pixels, resampling handles any ratio
+        let size_x_supersized = (size_x as f64 * ssaa_fac).round().max(1.0);
+        let size_y_supersized = (size_y as f64 * ssaa_fac).round().max(1.0);
 
         let size_x_supersized_half = (size_x_supersized / 2.0) as usize;
         let size_y_supersized_half = (size_y_supersized / 2.0) as usize;
 
         let z_buffer_supersized = vec![f64::MAX; (size_x_supersized * size_y_supersized) as usize];
-        let buffer_supersized = vec![
-            crate::graphics::colors::color_vec_to_u32(bg_color);
-            (size_x_supersized * size_y_supersized) as usize
-        ];
-        return (
+        let normal_buffer_supersized =
+            vec![Vector3d::zero(); (size_x_supersized * size_y_supersized) as usize];
+        let buffer_supersized = vec![*bg_color; (size_x_supersized * size_y_supersized) as usize];
+        Ok((
             ssaa_fac,
             size_x_supersized as usize,
             size_y_supersized as usize,
@@ -151,10 +345,11 @@ impl Canvas {
             size_y_supersized_half,
             buffer_supersized,
             z_buffer_supersized,
-        );
+            normal_buffer_supersized,
+        ))
     }
 
-    pub fn set_ssaa(&mut self, ssaa: SSAA) {
+    pub fn set_ssaa(&mut self, ssaa: SSAA) -> Result<(), RenderError> {
         let (
             ssaa_fac,
             size_x_supersized,
@@ -163,7 +358,13 @@ impl Canvas {
             size_y_supersized_half,
             buffer_supersized,
             z_buffer_supersized,
-        ) = Self::calc_ssaa_variables(&ssaa, self.size_x, self.size_y, &self.bg_color);
+            normal_buffer_supersized,
+        ) = Self::calc_ssaa_variables(
+            &ssaa,
+            self.size_x,
+            self.size_y,
+            &premultiply(&self.linear_color(&self.bg_color)),
+        )?;
         self.ssaa = ssaa;
         self.ssaa_fac = ssaa_fac;
         self.size_x_supersized = size_x_supersized;
@@ -172,53 +373,347 @@ impl Canvas {
         self.size_y_supersized_half = size_y_supersized_half;
         self.buffer_supersized = buffer_supersized;
         self.z_buffer_supersized = z_buffer_supersized;
+        self.normal_buffer_supersized = normal_buffer_supersized;
+        if self.id_buffer_supersized.is_some() {
+            self.id_buffer_supersized = Some(vec![None; size_x_supersized * size_y_supersized]);
+        }
+        if self.g_buffer_supersized.is_some() {
+            self.g_buffer_supersized = Some(GBuffer::new(size_x_supersized * size_y_supersized));
+        }
+        self.reset_accumulation();
+        Ok(())
+    }
+
+    pub fn set_id_buffer_enabled(&mut self, enabled: bool) {
+        // the ID buffer costs memory and a write per pixel, so it is off by default
+        self.id_buffer_supersized = if enabled {
+            Some(vec![None; self.size_x_supersized * self.size_y_supersized])
+        } else {
+            None
+        };
+    }
+
+    pub fn set_ssao(&mut self, ssao: Option<Ssao>) {
+        self.ssao = ssao;
+        self.update_g_buffer_allocation();
+    }
+
+    pub fn set_deferred(&mut self, deferred: bool) {
+        self.deferred = deferred;
+        self.update_g_buffer_allocation();
+    }
+
+    pub fn set_aovs_enabled(&mut self, enabled: bool) {
+        self.aovs_enabled = enabled;
+        self.update_g_buffer_allocation();
+    }
+
+    fn update_g_buffer_allocation(&mut self) {
+        // the G-buffer lives as long as deferred shading, SSAO or the render passes need it
+        let needed = self.deferred || self.ssao.is_some() || self.aovs_enabled;
+        if !needed {
+            self.g_buffer_supersized = None;
+        } else if self.g_buffer_supersized.is_none() {
+            self.g_buffer_supersized = Some(GBuffer::new(
+                self.size_x_supersized * self.size_y_supersized,
+            ));
+        }
+    }
+
+    pub fn set_selection_outline(&mut self, outline: Option<Outline>) {
+        self.selection_outline = outline;
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+        self.reset_accumulation();
+    }
+
+    pub fn reset_accumulation(&mut self) {
+        // call after changing the scene, camera or lights while path tracing
+        self.accumulation_buffer_supersized.fill(Vector3d::zero());
+        self.accumulated_samples = 0;
+        self.accumulation_time = Duration::ZERO;
+    }
+
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping, exposure: f64) {
+        self.tone_mapping = tone_mapping;
+        self.exposure = exposure;
+    }
+
+    pub fn set_srgb_enabled(&mut self, enabled: bool) {
+        self.srgb = enabled;
+        self.reset_accumulation();
+    }
+
+    pub fn linear_color(&self, color: &Vector4d) -> Vector4d {
+        // an input color as used for shading, decoded unless sRGB is disabled
+        if self.srgb {
+            color_vec_srgb_to_linear(color)
+        } else {
+            *color
+        }
+    }
+
+    pub fn linear_mesh_color(&self, mesh_idx: usize) -> Vector4d {
+        // shading color of a mesh, tinted with the highlight color while selected
+        let mesh = &self.scene.meshes()[mesh_idx];
+        self.linear_color(&if self.selected_mesh == Some(mesh_idx) {
+            mesh.color * 0.5 + self.highlight_color * 0.5
+        } else {
+            mesh.color
+        })
+    }
+
+    pub fn set_shading_mode(&mut self, shading_mode: ShadingMode) {
+        self.shading_mode = shading_mode;
+    }
+
+    pub fn set_outline(&mut self, outline: Option<Outline>) {
+        self.outline = outline;
     }
 
-    pub fn increase_ssaa(&mut self) {
-        match self.ssaa {
-            SSAA::X0_125 => self.set_ssaa(SSAA::X0_25),
-            SSAA::X0_25 => self.set_ssaa(SSAA::X1),
-            SSAA::X1 => self.set_ssaa(SSAA::X4),
-            SSAA::X4 => self.set_ssaa(SSAA::X16),
-            SSAA::X16 => self.set_ssaa(SSAA::X64),
-            SSAA::X64 => return,
+    pub fn set_fxaa(&mut self, fxaa: Option<Fxaa>) {
+        self.fxaa = fxaa;
+    }
+
+    pub fn set_msaa(&mut self, msaa: Option<Msaa>) {
+        // sample buffers are (re)allocated by the next render
+        self.msaa = msaa;
+        if self.msaa.is_none() {
+            self.msaa_color_samples = vec![];
+            self.msaa_depth_samples = vec![];
+            self.msaa_touched = vec![];
         }
     }
 
-    pub fn decrease_ssaa(&mut self) {
-        match self.ssaa {
-            SSAA::X0_125 => return,
-            SSAA::X0_25 => self.set_ssaa(SSAA::X0_125),
-            SSAA::X1 => self.set_ssaa(SSAA::X0_25),
-            SSAA::X4 => self.set_ssaa(SSAA::X1),
-            SSAA::X16 => self.set_ssaa(SSAA::X4),
-            SSAA::X64 => self.set_ssaa(SSAA::X16),
+    pub fn add_post_effect(&mut self, effect: impl PostEffect + 'static) {
+        self.post_effects.push(Arc::new(effect));
+    }
+
+    pub fn set_post_effects(&mut self, post_effects: PostEffectChain) {
+        self.post_effects = post_effects;
+    }
+
+    pub fn set_post_effects_enabled(&mut self, enabled: bool) {
+        self.post_effects_enabled = enabled;
+    }
+
+    pub fn increase_ssaa(&mut self) -> Result<(), RenderError> {
+        // next preset above the current factor, custom factors snap onto the presets
+        let fac = self.ssaa.fac();
+        match SSAA::PRESETS.into_iter().find(|preset| preset.fac() > fac) {
+            Some(ssaa) => self.set_ssaa(ssaa),
+            None => Ok(()),
         }
     }
 
+    pub fn decrease_ssaa(&mut self) -> Result<(), RenderError> {
+        let fac = self.ssaa.fac();
+        match SSAA::PRESETS
+            .into_iter()
+            .rev()
+            .find(|preset| preset.fac() < fac)
+        {
+            Some(ssaa) => self.set_ssaa(ssaa),
+            None => Ok(()),
+        }
+    }
+
+    pub fn set_ssaa_filter(&mut self, filter: ReconstructionFilter) {
+        self.ssaa_filter = filter;
+    }
+
+    pub fn set_upscale_filter(&mut self, filter: UpscaleFilter) {
+        self.upscale_filter = filter;
+    }
+
     pub fn reset(&mut self) {
         self.buffer.fill(color_vec_to_u32(&self.bg_color));
-        self.buffer_supersized
-            .fill(color_vec_to_u32(&self.bg_color));
+        let bg_color = premultiply(&self.linear_color(&self.bg_color));
+        self.buffer_supersized.fill(bg_color);
     }
 
     pub fn reset_z_buffer(&mut self) {
         self.z_buffer_supersized.fill(f64::MAX);
+        self.normal_buffer_supersized.fill(Vector3d::zero());
+        if let Some(id_buffer) = self.id_buffer_supersized.as_mut() {
+            id_buffer.fill(None);
+        }
+        if let Some(g_buffer) = self.g_buffer_supersized.as_mut() {
+            g_buffer.clear();
+        }
+        self.transparent_fragments.clear();
     }
 
     pub fn integer_coords_in_canvas(&self, x: i32, y: i32) -> bool {
         return x >= 0
-            && (x as usize) < self.size_y_supersized
+            && (x as usize) < self.size_x_supersized
             && y >= 0
             && (y as usize) < self.size_y_supersized;
     }
 
     pub fn add_point_light(&mut self, light: PointLight) {
         self.lights.push(light);
+        self.reset_accumulation();
     }
 
     pub fn add_mesh(&mut self, mesh: Mesh) {
         self.scene.add_mesh(mesh);
+        self.reset_accumulation();
+    }
+
+    pub fn add_sdf_shape(&mut self, shape: SdfShape) {
+        self.scene.add_sdf_shape(shape);
+        self.reset_accumulation();
+    }
+
+    pub fn set_mesh_transform(&mut self, mesh_idx: usize, transform: Matrix4x4) {
+        // moves the mesh through the scene, so the BVH is refit and traced samples restart
+        self.scene.set_mesh_transform(mesh_idx, transform);
+        self.reset_accumulation();
+    }
+
+    pub fn pick(&mut self, px: f64, py: f64) -> Option<RayHit> {
+        /*
+            mesh and face under output pixel (px, py), y pointing down
+        */
+        let ray = self.camera.calc_pixel_ray(px, py, self.size_x, self.size_y);
+        self.scene.pick(&ray)
+    }
+
+    pub fn select_at(&mut self, px: f64, py: f64) -> Option<RayHit> {
+        // picks and selects the hit mesh for highlighting, clears the selection on a miss
+        let hit = self.pick(px, py);
+        self.set_selected_mesh(hit.map(|hit| hit.mesh));
+        hit
+    }
+
+    pub fn id_at(&self, px: usize, py: usize) -> Option<ObjectId> {
+        /*
+            mesh and face that produced output pixel (px, py) in the last frame, y pointing down.
+            constant time lookup in the ID buffer, None if it is disabled or nothing was drawn there
+        */
+        let id_buffer = self.id_buffer_supersized.as_ref()?;
+        if px >= self.size_x || py >= self.size_y {
+            return None;
+        }
+        id_buffer[self.supersized_center_index(px, py)]
+    }
+
+    pub fn supersized_center_index(&self, px: usize, py: usize) -> usize {
+        // index of the supersized pixel at the center of output pixel (px, py), y pointing down
+        let x_ = (((px as f64 + 0.5) * self.size_x_supersized as f64 / self.size_x as f64)
+            as usize)
+            .min(self.size_x_supersized - 1);
+        let y_ = (((py as f64 + 0.5) * self.size_y_supersized as f64 / self.size_y as f64)
+            as usize)
+            .min(self.size_y_supersized - 1);
+        y_ * self.size_x_supersized + x_
+    }
+
+    pub fn select_id_at(&mut self, px: usize, py: usize) -> Option<ObjectId> {
+        // like select_at, but reads the ID buffer instead of casting a ray
+        let id = self.id_at(px, py);
+        self.set_selected_mesh(id.map(|id| id.mesh));
+        id
+    }
+
+    pub fn set_selected_mesh(&mut self, mesh: Option<usize>) {
+        // the highlight changes colors, so traced samples so far are outdated
+        if self.selected_mesh != mesh {
+            self.selected_mesh = mesh;
+            self.reset_accumulation();
+        }
+    }
+
+    pub fn object_mask(&self, mesh: usize) -> Option<Vec<u8>> {
+        /*
+            coverage of the mesh per output pixel (0..255) in the last frame,
+            the share of supersized pixels in the footprint that belong to the mesh
+        */
+        let id_buffer = self.id_buffer_supersized.as_ref()?;
+        let mut mask = vec![0; self.size_x * self.size_y];
+        for y in 0..self.size_y {
+            let (y_lower, y_upper) =
+                self.supersized_footprint(y, self.size_y, self.size_y_supersized);
+            for x in 0..self.size_x {
+                let (x_lower, x_upper) =
+                    self.supersized_footprint(x, self.size_x, self.size_x_supersized);
+                let mut covered = 0;
+                for y_ in y_lower..y_upper {
+                    for x_ in x_lower..x_upper {
+                        if id_buffer[y_ * self.size_x_supersized + x_]
+                            .is_some_and(|id| id.mesh == mesh)
+                        {
+                            covered += 1;
+                        }
+                    }
+                }
+                let total = (y_upper - y_lower) * (x_upper - x_lower);
+                mask[y * self.size_x + x] = (covered * 255 / total) as u8;
+            }
+        }
+        Some(mask)
+    }
+
+    pub fn save_object_mask(&self, mesh: usize, path: &str) -> Result<(), RenderError> {
+        let mask = self.object_mask(mesh).ok_or_else(|| {
+            RenderError::InvalidArgument(
+                "object masks need the ID buffer to be enabled".to_string(),
+            )
+        })?;
+        save_mask(&mask, self.size_x, self.size_y, path)
+    }
+
+    pub fn save_object_masks(&self, path: &str) -> Result<(), RenderError> {
+        /*
+            one mask per mesh of the scene, a "{mesh}" in the path is replaced by the mesh index
+        */
+        if self.scene.meshes().len() > 1 && !path.contains("{mesh}") {
+            return Err(RenderError::InvalidArgument(format!(
+                "\"{}\" needs a {{mesh}} placeholder for {} meshes",
+                path,
+                self.scene.meshes().len()
+            )));
+        }
+        for mesh in 0..self.scene.meshes().len() {
+            self.save_object_mask(mesh, &path.replace("{mesh}", &mesh.to_string()))?;
+        }
+        Ok(())
+    }
+
+    pub fn aov(&self, aov: Aov, format: AovFormat) -> Result<Vec<Vector4d>, RenderError> {
+        // render pass of the last frame per output pixel, encoded for the format
+        calc_aov(self, aov, format)
+    }
+
+    pub fn save_aov(&self, aov: Aov, format: AovFormat, path: &str) -> Result<(), RenderError> {
+        save_aov_image(
+            &self.aov(aov, format)?,
+            self.size_x,
+            self.size_y,
+            format,
+            path,
+        )
+    }
+
+    pub fn save_aovs(&self, path: &str, format: AovFormat) -> Result<(), RenderError> {
+        /*
+            all render passes of the last frame, an "{aov}" in the path is replaced by the
+            name of the pass. albedo needs set_aovs_enabled and IDs the ID buffer
+        */
+        if !path.contains("{aov}") {
+            return Err(RenderError::InvalidArgument(format!(
+                "\"{}\" needs an {{aov}} placeholder for {} passes",
+                path,
+                Aov::ALL.len()
+            )));
+        }
+        for aov in Aov::ALL {
+            self.save_aov(aov, format, &path.replace("{aov}", &aov.to_string()))?;
+        }
+        Ok(())
     }
 
     pub fn set_pixel(&mut self, coords: (i32, i32), color: &Vector4d) {
@@ -228,120 +723,148 @@ impl Canvas {
                 * self.size_x_supersized as i32
                 + coords.0) as usize;
 
-            let color_from = &color_vec_from_u32(self.buffer_supersized[integer_coord_in_buffer]);
+            let color_from = &self.buffer_supersized[integer_coord_in_buffer];
 
-            // alpha-blend
+            // alpha-blend, the buffer is premultiplied
             self.buffer_supersized[integer_coord_in_buffer] =
-                color_vec_to_u32(&alpha_blend(color_from, &color));
-        } else {
-            println!("Drawing outside of canvas!");
+                alpha_blend(color_from, &premultiply(color));
         }
     }
 
-    pub fn set_pixel_with_z(&mut self, coords: (i32, i32), z: f64, color: &Vector4d) {
-        // only draw pixel if it is in buffer bounds, will pass silently
+    pub fn set_pixel_with_z(
+        &mut self,
+        coords: (i32, i32),
+        z: f64,
+        surface: &Surface,
+        color: &Vector4d,
+        id: Option<ObjectId>,
+    ) {
+        /*
+            only draw pixel if it is in buffer bounds, will pass silently.
+            surface holds the unlit attributes, color the shaded result.
+            translucent fragments are deferred to resolve_transparency and leave all
+            other buffers untouched, so they never hide what is behind them
+        */
         if self.integer_coords_in_canvas(coords.0, coords.1) {
-            let integer_coord_in_buffer = ((self.size_x_supersized as i32 - 1 - coords.1)
+            let integer_coord_in_buffer = ((self.size_y_supersized as i32 - 1 - coords.1)
                 * self.size_x_supersized as i32
                 + coords.0) as usize;
 
-            if z < self.z_buffer_supersized[integer_coord_in_buffer] {
+            if color.u < 1.0 {
+                if z < self.z_buffer_supersized[integer_coord_in_buffer] {
+                    self.transparent_fragments.push(TransparentFragment::new(
+                        integer_coord_in_buffer,
+                        z,
+                        *color,
+                    ));
+                }
+            } else if self.set_surface_with_z(integer_coord_in_buffer, z, surface, id) {
                 self.set_pixel(coords, color);
-                self.z_buffer_supersized[integer_coord_in_buffer] = z;
             }
         }
     }
 
-    pub fn add_layer(&mut self, layer: Canvas, pos_x: u32, pos_y: u32) {
-        if pos_x + layer.size_x as u32 >= self.size_x as u32
-            || pos_y + layer.size_y as u32 >= self.size_y as u32
-        {
-            println!("Layer too large for canvas!");
+    pub fn set_surface_with_z(
+        &mut self,
+        idx: usize,
+        z: f64,
+        surface: &Surface,
+        id: Option<ObjectId>,
+    ) -> bool {
+        /*
+            depth tests an opaque surface at an index of the supersized buffers and writes
+            depth, normal, ID and G-buffer, but no color. true if it passed
+        */
+        if z >= self.z_buffer_supersized[idx] {
+            return false;
         }
+        self.z_buffer_supersized[idx] = z;
+        self.normal_buffer_supersized[idx] = surface.normal;
+        if let Some(id_buffer) = self.id_buffer_supersized.as_mut() {
+            id_buffer[idx] = id;
+        }
+        if let Some(g_buffer) = self.g_buffer_supersized.as_mut() {
+            g_buffer.set(idx, surface, id.map(|id| id.mesh));
+        }
+        true
+    }
 
-        for y in 0..layer.size_y {
-            for x in 0..layer.size_x {
-                self.buffer[(pos_y as usize + y) * self.size_x + pos_x as usize + x] =
-                    layer.buffer[y * layer.size_x + x];
+    pub fn add_layer(
+        &mut self,
+        layer: &Canvas,
+        pos_x: i32,
+        pos_y: i32,
+        opacity: f64,
+        blend_mode: BlendMode,
+    ) {
+        /*
+            composites the resolved buffer of another canvas onto this one's, with its top left
+            corner at pos (y pointing down like the buffer rows). the layer is clipped to this
+            canvas, so it may hang over any border. call after rendering, render overwrites buffer
+        */
+        let x_lower = pos_x.max(0);
+        let y_lower = pos_y.max(0);
+        let x_upper = (pos_x + layer.size_x as i32).min(self.size_x as i32);
+        let y_upper = (pos_y + layer.size_y as i32).min(self.size_y as i32);
+
+        for y in y_lower..y_upper {
+            for x in x_lower..x_upper {
+                let idx = y as usize * self.size_x + x as usize;
+                let layer_idx = (y - pos_y) as usize * layer.size_x + (x - pos_x) as usize;
+                self.buffer[idx] = composite_pixel(
+                    self.buffer[idx],
+                    layer.buffer[layer_idx],
+                    opacity,
+                    blend_mode,
+                );
             }
         }
     }
 
+    pub fn apply_post_effects(&mut self) {
+        // FXAA directly on the packed buffer, then the chain in [0.0, ... 1.0] rgba
+        if let Some(fxaa) = self.fxaa {
+            fxaa.apply_packed(&mut self.buffer, self.size_x, self.size_y);
+        }
+        if !self.post_effects_enabled || self.post_effects.is_empty() {
+            return;
+        }
+        let mut pixels: Vec<Vector4d> = self
+            .buffer
+            .iter()
+            .map(|val| color_vec_from_u32(*val))
+            .collect();
+        for effect in &self.post_effects {
+            effect.apply(&mut pixels, self.size_x, self.size_y);
+        }
+        for (val, pixel) in self.buffer.iter_mut().zip(&pixels) {
+            *val = color_vec_to_u32(pixel);
+        }
+    }
+
     pub fn apply_ssaa(&mut self) {
-        if self.ssaa_fac >= 1.0 {
-            // SSAA in antialiasing mode
-            for y in 0..self.size_y {
-                for x in 0..self.size_x {
-                    let mut mixed = Vector4d::zeros();
-                    for y_ in 0..self.ssaa_fac as usize {
-                        for x_ in 0..self.ssaa_fac as usize {
-                            mixed += color_vec_from_u32(
-                                self.buffer_supersized[(self.ssaa_fac as usize * y + y_)
-                                    * self.size_x_supersized
-                                    + (self.ssaa_fac as usize * x + x_)],
-                            );
-                        }
-                    }
-                    mixed /= self.ssaa_fac as f64 * self.ssaa_fac as f64;
-                    self.buffer[y * self.size_x + x] = color_vec_to_u32(&mixed);
-                }
-            }
-        } else {
-            // SSAA in upscaling mode
-            if self.ssaa_fac == 0.5 {
-                for y_ in 0..self.size_y_supersized {
-                    for x_ in 0..self.size_x_supersized {
-                        self.buffer[y_ * 2 * self.size_x + x_ * 2] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[(y_ * 2 + 1) * self.size_x + x_ * 2] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[y_ * 2 * self.size_x + (x_ * 2 + 1)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[(y_ * 2 + 1) * self.size_x + (x_ * 2 + 1)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                    }
-                }
-            } else if self.ssaa_fac == 0.25 {
-                for y_ in 0..self.size_y_supersized {
-                    for x_ in 0..self.size_x_supersized {
-                        self.buffer[(y_ * 4) * self.size_x + (x_ * 4)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[(y_ * 4 + 1) * self.size_x + (x_ * 4)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[(y_ * 4 + 2) * self.size_x + (x_ * 4)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[(y_ * 4 + 3) * self.size_x + (x_ * 4)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-
-                        self.buffer[(y_ * 4) * self.size_x + (x_ * 4 + 1)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[(y_ * 4 + 1) * self.size_x + (x_ * 4 + 1)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[(y_ * 4 + 2) * self.size_x + (x_ * 4 + 1)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[(y_ * 4 + 3) * self.size_x + (x_ * 4 + 1)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-
-                        self.buffer[(y_ * 4) * self.size_x + (x_ * 4 + 2)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[(y_ * 4 + 1) * self.size_x + (x_ * 4 + 2)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[(y_ * 4 + 2) * self.size_x + (x_ * 4 + 2)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[(y_ * 4 + 3) * self.size_x + (x_ * 4 + 2)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-
-                        self.buffer[(y_ * 4) * self.size_x + (x_ * 4 + 3)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[(y_ * 4 + 1) * self.size_x + (x_ * 4 + 3)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[(y_ * 4 + 2) * self.size_x + (x_ * 4 + 3)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                        self.buffer[(y_ * 4 + 3) * self.size_x + (x_ * 4 + 3)] =
-                            self.buffer_supersized[y_ * self.size_x_supersized + x_];
-                    }
-                }
+        /*
+            resolves the linear HDR supersized buffer into the displayable buffer.
+            filters the supersized pixels around each output pixel with the reconstruction filter
+            (antialiasing mode) or interpolates them with the upscale filter (upscaling mode),
+            then applies exposure and tone mapping
+        */
+        let resampled = resample(
+            &self.buffer_supersized,
+            (self.size_x_supersized, self.size_y_supersized),
+            (self.size_x, self.size_y),
+            self.ssaa_filter,
+            self.upscale_filter,
+        );
+        for (pixel, mixed) in self.buffer.iter_mut().zip(resampled) {
+            // filtered premultiplied, so edges against a transparent background stay clean
+            let mut color = self
+                .tone_mapping
+                .apply(&unpremultiply(&mixed), self.exposure);
+            if self.srgb {
+                color = color_vec_linear_to_srgb(&color);
             }
+            *pixel = color_vec_to_u32(&color);
         }
     }
 
@@ -349,6 +872,7 @@ impl Canvas {
         &mut self,
         points: &Vec<IntegerVector2d>,
         light_cam_space_reallight: &Vec<PointLight>,
+        id: Option<ObjectId>,
     ) {
         /*
             implements scanline algorithm with some extended features/bugs courtesy of yours truly.
@@ -483,7 +1007,6 @@ impl Canvas {
                     }
 
                     while cur_x <= edge2.x_intersect {
-                        // call fragment shader
                         let z_projected = cur_attrs[3];
                         let color = color_vec_from_f64(
                             cur_attrs[7],
@@ -493,15 +1016,33 @@ impl Canvas {
                         );
                         let x = Vector3d::new(cur_attrs[0], cur_attrs[1], cur_attrs[2]);
                         let n = Vector3d::new(cur_attrs[4], cur_attrs[5], cur_attrs[6]).normalize();
-                        // let l = (light_cam_space - x).normalize();
-                        let v = (x * -1.0).normalize();
-                        let phong_color = phong_frag(x, n, v, color, &light_cam_space_reallight);
-
-                        self.set_pixel_with_z(
-                            (cur_x.round() as i32, y_scan),
-                            z_projected,
-                            &phong_color,
-                        );
+                        let surface = Surface::new(x, n, color);
+                        let coords = (cur_x.round() as i32, y_scan);
+
+                        if self.deferred
+                            && color.u >= 1.0
+                            && self.integer_coords_in_canvas(coords.0, coords.1)
+                        {
+                            // deferred, only the surface goes to the G-buffer, lit in shade_g_buffer
+                            let idx = (self.size_y_supersized - 1 - coords.1 as usize)
+                                * self.size_x_supersized
+                                + coords.0 as usize;
+                            self.set_surface_with_z(idx, z_projected, &surface, id);
+                        } else {
+                            // call fragment shader
+                            // let l = (light_cam_space - x).normalize();
+                            let v = (x * -1.0).normalize();
+                            let frag_color = shade_frag(
+                                self.shading_mode,
+                                x,
+                                n,
+                                v,
+                                color,
+                                light_cam_space_reallight,
+                            );
+
+                            self.set_pixel_with_z(coords, z_projected, &surface, &frag_color, id);
+                        }
                         cur_x += 1.0;
                         for i in 0..cur_attrs.len() {
                             cur_attrs[i] += dattrs[i];
@@ -525,46 +1066,123 @@ impl Canvas {
         }
     }
 
+    pub fn render(&mut self) {
+        // renders the scene with the selected renderer, both write into the same buffers
+        match self.renderer {
+            Renderer::Rasterizer => self.render_scene_to_buffer(),
+            Renderer::RayTracer(ray_tracer) => ray_tracer.render(self),
+            Renderer::PathTracer(path_tracer) => path_tracer.render(self),
+        }
+    }
+
     pub fn render_scene_to_buffer(&mut self) {
         // camera space stuff
         // let mut e = Vector3d::new(5.0, 5.0, 1.0) * 2.0; // cam pos
 
-        let (g, u, v, w) = self.camera.calc_guvw();
+        let (_, _, _, w) = self.camera.calc_guvw();
 
-        let camera_matrix = Matrix4x4::from_vecs(
-            Vector4d::from_vector3d(&u, -u.dot(self.camera.e)),
-            Vector4d::from_vector3d(&v, -v.dot(self.camera.e)),
-            Vector4d::from_vector3d(&w, -w.dot(self.camera.e)),
-            Vector4d::new(0.0, 0.0, 0.0, 1.0),
-        );
+        let camera_matrix = self.camera.calc_camera_matrix();
 
         // transform lights to camera space
 
         let mut lights_cam_space_reallight = self.lights.clone();
         for light in lights_cam_space_reallight.iter_mut() {
+            light.emission = self.linear_color(&light.emission);
             light.pos = camera_matrix
                 .times_vec(Vector4d::from_vector3d(&light.pos, 1.0))
                 .truncate_to_3d();
         }
 
-        for mesh in self.scene.meshes.clone() {
-            for face in mesh.faces.iter() {
+        // frustum culling, first whole meshes by their bounds, then BVH nodes of the remaining ones
+        let frustum = self.camera.calc_frustum();
+        let mut stats = RenderStats::default();
+        let mut mesh_visible = vec![false; self.scene.meshes().len()];
+        for (mesh_idx, mesh) in self.scene.meshes().iter().enumerate() {
+            stats.triangles_total += mesh.faces.len();
+            if frustum.intersects_aabb(&mesh.world_bounds()) {
+                mesh_visible[mesh_idx] = true;
+                stats.meshes_drawn += 1;
+            } else {
+                stats.meshes_culled += 1;
+            }
+        }
+
+        let mut face_visible: Vec<Vec<bool>> = self
+            .scene
+            .meshes()
+            .iter()
+            .map(|mesh| vec![false; mesh.faces.len()])
+            .collect();
+        if stats.meshes_drawn > 0 {
+            self.scene.bvh().traverse(
+                |bounds| frustum.intersects_aabb(bounds),
+                |primitive| {
+                    if mesh_visible[primitive.mesh] {
+                        face_visible[primitive.mesh][primitive.face] = true;
+                    }
+                },
+            );
+        }
+
+        // multisampled triangles go to the sample buffers first, they start out empty
+        let msaa = self.msaa.clone();
+        if let Some(msaa) = &msaa {
+            let sample_count = self.buffer_supersized.len() * msaa.sample_count();
+            self.msaa_color_samples
+                .resize(sample_count, Vector4d::zeros());
+            self.msaa_depth_samples.resize(sample_count, f64::MAX);
+            self.msaa_touched.clear();
+            self.msaa_touched
+                .resize(self.buffer_supersized.len(), false);
+        }
+
+        for mesh_idx in 0..self.scene.meshes().len() {
+            if !mesh_visible[mesh_idx] {
+                stats.triangles_frustum_culled += self.scene.meshes()[mesh_idx].faces.len();
+                continue;
+            }
+            // copy out only what the loop needs, drawing borrows the canvas mutably
+            let mesh = &self.scene.meshes()[mesh_idx];
+            let world_vertices = mesh.world_vertices();
+            let world_normals: Vec<Vector3d> = if self.render_smooth {
+                mesh.vertex_normals
+                    .iter()
+                    .map(|normal| mesh.world_normal(normal))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            let mesh_color = self.linear_mesh_color(mesh_idx);
+            for (face_idx, visible) in face_visible[mesh_idx].iter().enumerate() {
+                if !visible {
+                    stats.triangles_frustum_culled += 1;
+                    continue;
+                }
+                let face = &self.scene.meshes()[mesh_idx].faces[face_idx];
+                let face = [face[0], face[1], face[2]];
+
                 let triangle = Triangle3d::new(
-                    mesh.vertices[face[0]],
-                    mesh.vertices[face[1]],
-                    mesh.vertices[face[2]],
-                    &mesh.color,
+                    world_vertices[face[0]],
+                    world_vertices[face[1]],
+                    world_vertices[face[2]],
+                    &mesh_color,
                 );
                 // println!("{}", triangle);
 
-                // backface culling
+                // backface culling, translucent meshes keep their back faces, they show through
                 // Everlast - The Culling is Coming  =>   https://www.youtube.com/watch?v=yWYsbxkhlpU
-                if w.dot(triangle.normal) < 0.0 {
+                // with MSAA, opaque triangles are culled by their winding on screen instead, the view
+                // direction misses faces near the silhouette that exact coverage would leave as holes
+                let cull_in_raster = msaa.is_some() && mesh_color.u >= 1.0;
+                let back_facing = !cull_in_raster && w.dot(triangle.normal) < 0.0;
+                if back_facing && mesh_color.u >= 1.0 {
+                    stats.triangles_backface_culled += 1;
                     continue;
                 }
 
                 let mut skip_triangle = false;
                 let mut triangle_projected = vec![IntegerVector2d::zero(); 3];
+                let mut triangle_raster = [(0.0, 0.0); 3]; // unrounded, for multisampling
                 for (i, vertex) in triangle.vertices.iter().enumerate() {
                     let vertex_homo = Vector4d::from_vector3d(vertex, 1.0); // hehe
 
@@ -585,14 +1203,17 @@ impl Canvas {
                     }
 
                     // store attributes like pos and normal while still in camera space
-                    let normal_cam_space;
+                    let mut normal_cam_space;
                     if self.render_smooth {
                         normal_cam_space = camera_matrix
-                            .times_vec(Vector4d::from_vector3d(&mesh.vertex_normals[face[i]], 0.0));
+                            .times_vec(Vector4d::from_vector3d(&world_normals[face[i]], 0.0));
                     } else {
                         normal_cam_space =
                             camera_matrix.times_vec(Vector4d::from_vector3d(&triangle.normal, 0.0));
                     }
+                    if back_facing {
+                        normal_cam_space *= -1.0;
+                    }
                     let mut attrs: Vec<f64> = vec![0.0; 11];
                     attrs[0] = vertex_cam_space.x;
                     attrs[1] = vertex_cam_space.y;
@@ -614,16 +1235,430 @@ impl Canvas {
                         attrs,
                     );
                     triangle_projected[i] = ivec2;
+                    triangle_raster[i] = (
+                        (vec3.x + 1.0) * self.size_x_supersized_half as f64,
+                        (vec3.y + 1.0) * self.size_y_supersized_half as f64,
+                    );
                 }
 
                 // cull triangles that is even partially out if bounds
                 if skip_triangle {
+                    stats.triangles_clipped += 1;
                     continue;
                 }
-                self.draw_polygon_onto_buffer(&triangle_projected, &lights_cam_space_reallight);
+                if cull_in_raster && signed_area(&triangle_raster) <= 0.0 {
+                    stats.triangles_backface_culled += 1;
+                    continue;
+                }
+                match &msaa {
+                    Some(msaa) => self.draw_triangle_multisampled(
+                        msaa,
+                        &triangle_raster,
+                        &triangle_projected,
+                        &lights_cam_space_reallight,
+                        Some(ObjectId::new(mesh_idx, face_idx)),
+                    ),
+                    None => self.draw_polygon_onto_buffer(
+                        &triangle_projected,
+                        &lights_cam_space_reallight,
+                        Some(ObjectId::new(mesh_idx, face_idx)),
+                    ),
+                }
+                stats.triangles_drawn += 1;
             }
         }
+        self.stats = stats;
+        if let Some(msaa) = &msaa {
+            self.resolve_msaa(msaa);
+        }
+        if self.deferred && msaa.is_none() {
+            self.shade_g_buffer(&lights_cam_space_reallight);
+        }
+
+        self.draw_sdf_shapes_onto_buffer(&camera_matrix, &lights_cam_space_reallight);
+        self.apply_ssao();
+        self.resolve_transparency();
 
         self.apply_ssaa();
+        // outlines before FXAA and the post chain, so they get anti-aliased as well
+        self.apply_outline();
+        self.apply_selection_outline();
+        self.apply_post_effects();
+    }
+
+    pub fn draw_triangle_multisampled(
+        &mut self,
+        msaa: &Msaa,
+        raster: &[(f64, f64); 3],
+        points: &[IntegerVector2d],
+        light_cam_space_reallight: &Vec<PointLight>,
+        id: Option<ObjectId>,
+    ) {
+        /*
+            tests coverage and depth at every sample position of the pixels under the triangle
+            and shades once per pixel at the centroid of the samples that passed. raster holds
+            the unrounded raster positions of the corners, points their attributes.
+            the pixel center is tested as well, it decides the per-pixel z, normal and ID.
+
+            barycentric weights and depth are planes in raster space, so along a row they are
+            stepped per sample with one addition instead of being recomputed
+        */
+        let area = signed_area(raster);
+        if area.abs() < f64::EPSILON {
+            return;
+        }
+        let planes = TrianglePlanes::new(raster, [0, 1, 2].map(|i| points[i].attrs[3]), area);
+        let interpolate = |weights: (f64, f64, f64), attr: usize| {
+            points[0].attrs[attr] * weights.0
+                + points[1].attrs[attr] * weights.1
+                + points[2].attrs[attr] * weights.2
+        };
+
+        let sample_count = msaa.sample_count();
+        let bg_color = premultiply(&self.linear_color(&self.bg_color));
+        let y_lower = raster
+            .iter()
+            .map(|p| p.1)
+            .fold(f64::MAX, f64::min)
+            .floor()
+            .max(0.0) as usize;
+        let y_upper = (raster.iter().map(|p| p.1).fold(f64::MIN, f64::max).ceil() as usize)
+            .min(self.size_y_supersized);
+
+        // per sample: barycentric weights and depth at the current pixel of the row
+        let mut row = vec![[0.0; 4]; sample_count];
+        let mut covered = vec![false; sample_count];
+        for y in y_lower..y_upper {
+            let Some((x_lower, x_upper)) = planes.row_span(y, self.size_x_supersized) else {
+                continue;
+            };
+            for (s, offset) in msaa.samples().iter().enumerate() {
+                row[s] = planes.at((x_lower as f64 + 0.5 + offset.0, y as f64 + 0.5 + offset.1));
+            }
+
+            for x in x_lower..x_upper {
+                if x > x_lower {
+                    for sample in row.iter_mut() {
+                        planes.step_x(sample);
+                    }
+                }
+                let idx = (self.size_y_supersized - 1 - y) * self.size_x_supersized + x;
+                let touched = self.msaa_touched[idx];
+
+                // coverage and depth per sample
+                let mut covered_count = 0;
+                let mut centroid = (0.0, 0.0);
+                for (s, offset) in msaa.samples().iter().enumerate() {
+                    let [w_1, w_2, w_3, z] = row[s];
+                    covered[s] = w_1 >= 0.0
+                        && w_2 >= 0.0
+                        && w_3 >= 0.0
+                        && (!touched || z < self.msaa_depth_samples[idx * sample_count + s]);
+                    if covered[s] {
+                        covered_count += 1;
+                        centroid = (centroid.0 + offset.0, centroid.1 + offset.1);
+                    }
+                }
+                if covered_count == 0 {
+                    continue;
+                }
+                let center = (x as f64 + 0.5, y as f64 + 0.5);
+                let centroid = (
+                    center.0 + centroid.0 / covered_count as f64,
+                    center.1 + centroid.1 / covered_count as f64,
+                );
+
+                // one fragment shader call per pixel
+                let [w_1, w_2, w_3, z_centroid] = planes.at(centroid);
+                let weights = (w_1, w_2, w_3);
+                let x_cam = Vector3d::new(
+                    interpolate(weights, 0),
+                    interpolate(weights, 1),
+                    interpolate(weights, 2),
+                );
+                let n = Vector3d::new(
+                    interpolate(weights, 4),
+                    interpolate(weights, 5),
+                    interpolate(weights, 6),
+                )
+                .normalize();
+                // flat per triangle, taken from a corner so opaque alpha stays exactly 1.0
+                let color = color_vec_from_f64(
+                    points[0].attrs[7],
+                    points[0].attrs[8],
+                    points[0].attrs[9],
+                    points[0].attrs[10],
+                );
+                let frag_color = shade_frag(
+                    self.shading_mode,
+                    x_cam,
+                    n,
+                    (x_cam * -1.0).normalize(),
+                    color,
+                    light_cam_space_reallight,
+                );
+
+                if frag_color.u < 1.0 {
+                    // translucent, to the A-buffer with its coverage folded into alpha
+                    let mut frag_color = frag_color;
+                    frag_color.u *= covered_count as f64 / sample_count as f64;
+                    self.transparent_fragments
+                        .push(TransparentFragment::new(idx, z_centroid, frag_color));
+                    continue;
+                }
+
+                let samples = idx * sample_count..(idx + 1) * sample_count;
+                if !touched {
+                    // first write this frame, the other samples still show the background
+                    self.msaa_color_samples[samples.clone()].fill(bg_color);
+                    self.msaa_depth_samples[samples.clone()].fill(f64::MAX);
+                    self.msaa_touched[idx] = true;
+                }
+                let premultiplied = premultiply(&frag_color);
+                for (s, sample_idx) in samples.enumerate().filter(|(s, _)| covered[*s]) {
+                    self.msaa_color_samples[sample_idx] = premultiplied;
+                    self.msaa_depth_samples[sample_idx] = row[s][3];
+                }
+
+                let [w_1, w_2, w_3, z_center] = planes.at(center);
+                if w_1 >= 0.0 && w_2 >= 0.0 && w_3 >= 0.0 {
+                    self.set_surface_with_z(idx, z_center, &Surface::new(x_cam, n, color), id);
+                }
+            }
+        }
+    }
+
+    pub fn resolve_msaa(&mut self, msaa: &Msaa) {
+        /*
+            averages the samples of every pixel into the supersized buffer. pixels whose center
+            no triangle covered take the depth of their nearest sample, so implicit shapes and
+            transparency still depth test against the edges. untouched pixels stay background
+        */
+        let sample_count = msaa.sample_count();
+        let bg_color = premultiply(&self.linear_color(&self.bg_color));
+        for idx in 0..self.buffer_supersized.len() {
+            if !self.msaa_touched[idx] {
+                self.buffer_supersized[idx] = bg_color;
+                continue;
+            }
+            let samples = idx * sample_count..(idx + 1) * sample_count;
+            let mut mixed = Vector4d::zeros();
+            for color in &self.msaa_color_samples[samples.clone()] {
+                mixed += *color;
+            }
+            self.buffer_supersized[idx] = mixed / sample_count as f64;
+            if self.z_buffer_supersized[idx] == f64::MAX {
+                self.z_buffer_supersized[idx] = self.msaa_depth_samples[samples]
+                    .iter()
+                    .fold(f64::MAX, |z_1, z_2| z_1.min(*z_2));
+            }
+        }
+    }
+
+    pub fn shade_g_buffer(&mut self, light_cam_space_reallight: &Vec<PointLight>) {
+        /*
+            lighting pass of deferred shading, runs the fragment shader once for every pixel
+            covered by an opaque mesh since the last reset_z_buffer
+        */
+        let Some(g_buffer) = &self.g_buffer_supersized else {
+            return;
+        };
+        for idx in 0..g_buffer.covered.len() {
+            if !g_buffer.covered[idx] || g_buffer.material[idx].is_none() {
+                continue;
+            }
+            let x = g_buffer.position[idx];
+            let frag_color = shade_frag(
+                self.shading_mode,
+                x,
+                self.normal_buffer_supersized[idx],
+                (x * -1.0).normalize(),
+                g_buffer.albedo[idx],
+                light_cam_space_reallight,
+            );
+            // opaque, covers the background completely
+            self.buffer_supersized[idx] = premultiply(&frag_color);
+        }
+    }
+
+    pub fn apply_ssao(&mut self) {
+        /*
+            darkens the ambient light of all opaque surfaces by their occlusion. shading is
+            linear in the light, so the blocked part is the albedo times the ambient share that
+            does not reach the surface. runs before transparency, glass does not occlude
+        */
+        let Some(ssao) = &self.ssao else {
+            return;
+        };
+        let Some(g_buffer) = &self.g_buffer_supersized else {
+            return;
+        };
+        let factors = ssao.calc_ambient_factors(
+            g_buffer,
+            &self.normal_buffer_supersized,
+            self.size_x_supersized,
+            self.size_y_supersized,
+            &self.camera.calc_perspective_projection_matrix(),
+        );
+        for ((pixel, albedo), factor) in self
+            .buffer_supersized
+            .iter_mut()
+            .zip(&g_buffer.albedo)
+            .zip(factors)
+        {
+            // the pixel is premultiplied, partially covered edges lose less
+            let blocked = AMBIENT * (1.0 - factor) * pixel.u;
+            pixel.x = (pixel.x - albedo.x * blocked).max(0.0);
+            pixel.y = (pixel.y - albedo.y * blocked).max(0.0);
+            pixel.z = (pixel.z - albedo.z * blocked).max(0.0);
+        }
+    }
+
+    pub fn resolve_transparency(&mut self) {
+        // blends the collected translucent fragments over the opaque image, back to front
+        let mut fragments = std::mem::take(&mut self.transparent_fragments);
+        resolve_transparent_fragments(
+            &mut fragments,
+            &mut self.buffer_supersized,
+            &self.z_buffer_supersized,
+        );
+        fragments.clear();
+        self.transparent_fragments = fragments;
+    }
+
+    pub fn draw_sdf_shapes_onto_buffer(
+        &mut self,
+        camera_matrix: &Matrix4x4,
+        light_cam_space_reallight: &Vec<PointLight>,
+    ) {
+        /*
+            sphere traces the implicit shapes of the scene per supersized pixel and shades
+            the hits like rasterized fragments, in camera space. the projected z goes through
+            the same depth test, so shapes and meshes occlude each other correctly
+        */
+        if self.scene.sdf_shapes.is_empty() {
+            return;
+        }
+        let projection_matrix = self.camera.calc_perspective_projection_matrix();
+        let (size_x, size_y) = (self.size_x_supersized, self.size_y_supersized);
+
+        let mut fragments = vec![];
+        for y in 0..size_y {
+            for x in 0..size_x {
+                let ray = self
+                    .camera
+                    .calc_pixel_ray(x as f64, y as f64, size_x, size_y);
+                let Some(hit) = sphere_trace(&self.scene.sdf_shapes, &ray) else {
+                    continue;
+                };
+
+                let x_cam_space = camera_matrix.times_vec(Vector4d::from_vector3d(&hit.point, 1.0));
+                let z_projected = projection_matrix.times_vec(x_cam_space).z;
+                let n = camera_matrix
+                    .times_vec(Vector4d::from_vector3d(&hit.normal, 0.0))
+                    .truncate_to_3d();
+                let x_cam_space = x_cam_space.truncate_to_3d();
+                let v = (x_cam_space * -1.0).normalize();
+                let albedo = self.linear_color(&self.scene.sdf_shapes[hit.shape].color);
+                let frag_color = shade_frag(
+                    self.shading_mode,
+                    x_cam_space,
+                    n,
+                    v,
+                    albedo,
+                    light_cam_space_reallight,
+                );
+
+                // buffer rows are flipped, pixel rays have y pointing down
+                fragments.push((
+                    (x as i32, (size_y - 1 - y) as i32),
+                    z_projected,
+                    Surface::new(x_cam_space, n, albedo),
+                    frag_color,
+                ));
+            }
+        }
+
+        for (coords, z, surface, color) in fragments {
+            self.set_pixel_with_z(coords, z, &surface, &color, None);
+        }
+    }
+
+    fn supersized_footprint(
+        &self,
+        coord: usize,
+        size: usize,
+        size_supersized: usize,
+    ) -> (usize, usize) {
+        // range of supersized pixels covered by an output pixel, at least one in upscaling mode
+        let ratio = size_supersized as f64 / size as f64;
+        let lower = (coord as f64 * ratio) as usize;
+        let upper = (((coord + 1) as f64 * ratio) as usize).max(lower + 1);
+        (lower.min(size_supersized - 1), upper.min(size_supersized))
+    }
+
+    fn draw_edges(&mut self, outline: &Outline, edges_supersized: &[bool]) {
+        // an output pixel is an edge if any supersized pixel in its footprint is one
+        let mut edges = vec![false; self.size_x * self.size_y];
+        for y in 0..self.size_y {
+            let (y_lower, y_upper) =
+                self.supersized_footprint(y, self.size_y, self.size_y_supersized);
+            for x in 0..self.size_x {
+                let (x_lower, x_upper) =
+                    self.supersized_footprint(x, self.size_x, self.size_x_supersized);
+                edges[y * self.size_x + x] = (y_lower..y_upper).any(|y_| {
+                    (x_lower..x_upper).any(|x_| edges_supersized[y_ * self.size_x_supersized + x_])
+                });
+            }
+        }
+
+        let outline_color = color_vec_to_u32(&outline.color);
+        for (pixel, is_edge) in
+            self.buffer
+                .iter_mut()
+                .zip(outline.widen(&edges, self.size_x, self.size_y))
+        {
+            if is_edge {
+                *pixel = outline_color;
+            }
+        }
+    }
+
+    pub fn apply_outline(&mut self) {
+        /*
+            draws the outline (if any) onto the resolved buffer
+        */
+        let Some(outline) = self.outline else {
+            return;
+        };
+
+        let edges_supersized = outline.detect_edges(
+            &self.z_buffer_supersized,
+            &self.normal_buffer_supersized,
+            self.size_x_supersized,
+            self.size_y_supersized,
+        );
+
+        self.draw_edges(&outline, &edges_supersized);
+    }
+
+    pub fn apply_selection_outline(&mut self) {
+        /*
+            draws the selection outline (if any) around the selected mesh, using the ID buffer
+        */
+        let (Some(outline), Some(mesh)) = (self.selection_outline, self.selected_mesh) else {
+            return;
+        };
+        let Some(id_buffer) = self.id_buffer_supersized.as_ref() else {
+            return;
+        };
+
+        let edges_supersized = detect_mesh_edges(
+            id_buffer,
+            mesh,
+            self.size_x_supersized,
+            self.size_y_supersized,
+        );
+        self.draw_edges(&outline, &edges_supersized);
     }
 }
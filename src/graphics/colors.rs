@@ -1,8 +1,16 @@
+use crate::error::RenderError;
 use crate::util::clamp;
 use crate::vectors::Vector4d;
 
-pub fn named_color(color: &str) -> Vector4d {
-    if color == "red" {
+/*
+    Colors given to the renderer (named colors, mesh, light and background colors, 8 bit values)
+    are sRGB encoded, just like the values picked in any color picker. With sRGB enabled on the
+    canvas they are decoded to linear light before shading, all lighting, blending and averaging
+    happens in linear space and the result is encoded again when resolving into Canvas::buffer.
+*/
+
+pub fn named_color(color: &str) -> Result<Vector4d, RenderError> {
+    let rgba = if color == "red" {
         Vector4d::new(1.0, 0.0, 0.0, 1.0)
     } else if color == "green" {
         Vector4d::new(0.0, 1.0, 0.0, 1.0)
@@ -19,32 +27,88 @@ pub fn named_color(color: &str) -> Vector4d {
     } else if color == "white" {
         Vector4d::new(1.0, 1.0, 1.0, 1.0)
     } else {
-        panic!("I don't have all your colors... {}", color);
-    }
+        return Err(RenderError::UnknownColor(color.to_string()));
+    };
+    Ok(rgba)
 }
 
+/*
+    Packed colors are 0xAARRGGBB with straight (not premultiplied) alpha, the layout
+    minifb expects (it ignores the alpha byte) and the one PNGs are written with.
+*/
+
 pub fn rgb_u8_to_u32(r: u8, g: u8, b: u8) -> u32 {
-    ((r as u32) << 16) | ((g as u32) << 8) | (b as u32)
+    // fully opaque
+    0xFF00_0000 | ((r as u32) << 16) | ((g as u32) << 8) | (b as u32)
 }
 
 pub fn rgb_f64_to_u32(r: f64, g: f64, b: f64) -> u32 {
+    rgba_f64_to_u32(r, g, b, 1.0)
+}
+
+pub fn rgba_f64_to_u32(r: f64, g: f64, b: f64, a: f64) -> u32 {
     /*
-       assumes r, g and b are all in range(0.0, ..., 1.0)
+       assumes r, g, b and a are all in range(0.0, ..., 1.0)
     */
-    (((r * 255.0) as u32) << 16) | (((g * 255.0) as u32) << 8) | ((b * 255.0) as u32)
+    (((a * 255.0) as u32) << 24)
+        | (((r * 255.0) as u32) << 16)
+        | (((g * 255.0) as u32) << 8)
+        | ((b * 255.0) as u32)
 }
 
 pub fn color_vec_to_u32(color: &Vector4d) -> u32 {
-    rgb_f64_to_u32(color.x, color.y, color.z)
+    rgba_f64_to_u32(color.x, color.y, color.z, color.u)
 }
 
 pub fn color_vec_from_u32(val: u32) -> Vector4d {
-    // Hard resets alpha to 1.0 (suboptimal, i know i know.)
+    let a = ((val >> 24) & 0xFF) as f64 / 255.0;
     let r = ((val >> 16) & 0xFF) as f64 / 255.0;
     let g = ((val >> 8) & 0xFF) as f64 / 255.0;
     let b = (val & 0xFF) as f64 / 255.0;
 
-    Vector4d::new(r, g, b, 1.0)
+    Vector4d::new(r, g, b, a)
+}
+
+pub fn color_vec_from_u32_srgb(val: u32) -> Vector4d {
+    // decodes an 8 bit sRGB value, e.g. a texel, to linear light
+    color_vec_srgb_to_linear(&color_vec_from_u32(val))
+}
+
+pub fn srgb_to_linear(c: f64) -> f64 {
+    // sRGB transfer function, inverted
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f64) -> f64 {
+    // sRGB transfer function, assumes c in range(0.0, ..., 1.0)
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn color_vec_srgb_to_linear(color: &Vector4d) -> Vector4d {
+    // alpha is linear in both spaces
+    Vector4d::new(
+        srgb_to_linear(color.x),
+        srgb_to_linear(color.y),
+        srgb_to_linear(color.z),
+        color.u,
+    )
+}
+
+pub fn color_vec_linear_to_srgb(color: &Vector4d) -> Vector4d {
+    Vector4d::new(
+        linear_to_srgb(color.x),
+        linear_to_srgb(color.y),
+        linear_to_srgb(color.z),
+        color.u,
+    )
 }
 
 pub fn color_vec_from_f64(r: f64, g: f64, b: f64, a: f64) -> Vector4d {
@@ -60,14 +124,36 @@ pub fn clamp_color(color: Vector4d) -> Vector4d {
     )
 }
 
+pub fn premultiply(color: &Vector4d) -> Vector4d {
+    let alpha = clamp(color.u);
+    Vector4d::new(color.x * alpha, color.y * alpha, color.z * alpha, alpha)
+}
+
+pub fn unpremultiply(color: &Vector4d) -> Vector4d {
+    // fully transparent pixels carry no color, they come out black
+    if color.u <= 0.0 {
+        return Vector4d::zeros();
+    }
+    Vector4d::new(
+        color.x / color.u,
+        color.y / color.u,
+        color.z / color.u,
+        color.u,
+    )
+}
+
 pub fn alpha_blend(color_1: &Vector4d, color_2: &Vector4d) -> Vector4d {
-    // simply setting new alpha to the one of the second input, not sure if that's right
-    clamp_color(Vector4d::new(
-        color_1.x * (1.0 - color_2.u) + color_2.x * color_2.u,
-        color_1.y * (1.0 - color_2.u) + color_2.y * color_2.u,
-        color_1.z * (1.0 - color_2.u) + color_2.z * color_2.u,
-        color_2.u,
-    ))
+    /*
+        porter-duff "over", color_2 on top of color_1, both premultiplied.
+        rgb is not clamped, the supersized buffer holds HDR values until tone mapping
+    */
+    let transmitted = 1.0 - clamp(color_2.u);
+    Vector4d::new(
+        color_2.x + color_1.x * transmitted,
+        color_2.y + color_1.y * transmitted,
+        color_2.z + color_1.z * transmitted,
+        color_2.u + color_1.u * transmitted,
+    )
 }
 
 pub fn apply_colored_lighting(base_color: &Vector4d, lighting_color: &Vector4d) -> Vector4d {
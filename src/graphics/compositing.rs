@@ -0,0 +1,135 @@
+use crate::graphics::colors::{color_vec_from_u32, color_vec_to_u32};
+use crate::util::clamp;
+use crate::vectors::Vector4d;
+use std::fmt;
+
+/*
+    Compositing of finished renders, e.g. picture-in-picture views or HUDs.
+
+    Works on the resolved, display encoded 0xAARRGGBB buffers with straight alpha. The blend
+    mode mixes the colors of layer and backdrop (like in image editors, on the encoded values),
+    the result is then laid over the backdrop with the layer's alpha times its opacity.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Add,
+    Overlay,
+}
+
+impl fmt::Display for BlendMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlendMode::Normal => write!(f, "Normal"),
+            BlendMode::Multiply => write!(f, "Multiply"),
+            BlendMode::Screen => write!(f, "Screen"),
+            BlendMode::Add => write!(f, "Add"),
+            BlendMode::Overlay => write!(f, "Overlay"),
+        }
+    }
+}
+
+impl BlendMode {
+    pub fn blend_channel(&self, backdrop: f64, source: f64) -> f64 {
+        match self {
+            BlendMode::Normal => source,
+            BlendMode::Multiply => backdrop * source,
+            BlendMode::Screen => backdrop + source - backdrop * source,
+            BlendMode::Add => (backdrop + source).min(1.0),
+            BlendMode::Overlay => {
+                if backdrop <= 0.5 {
+                    2.0 * backdrop * source
+                } else {
+                    1.0 - 2.0 * (1.0 - backdrop) * (1.0 - source)
+                }
+            }
+        }
+    }
+}
+
+pub fn composite_pixel(backdrop: u32, source: u32, opacity: f64, mode: BlendMode) -> u32 {
+    /*
+        source over backdrop, with the blended color where both are covered
+        and the plain source color where the backdrop is transparent
+    */
+    let b = color_vec_from_u32(backdrop);
+    let s = color_vec_from_u32(source);
+    let alpha_s = s.u * clamp(opacity);
+    if alpha_s <= 0.0 {
+        return backdrop;
+    }
+
+    let alpha = alpha_s + b.u * (1.0 - alpha_s);
+    let channel = |c_b: f64, c_s: f64| {
+        let mixed = (1.0 - b.u) * c_s + b.u * mode.blend_channel(c_b, c_s);
+        // premultiplied over, then back to straight alpha
+        clamp((mixed * alpha_s + c_b * b.u * (1.0 - alpha_s)) / alpha)
+    };
+    color_vec_to_u32(&Vector4d::new(
+        channel(b.x, s.x),
+        channel(b.y, s.y),
+        channel(b.z, s.z),
+        clamp(alpha),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKDROP: u32 = 0xFF808080;
+    const SOURCE: u32 = 0xFF404040;
+
+    fn assert_close(actual: u32, expected: u32) {
+        // allows one step of rounding per channel
+        let close = (0..4).all(|i| {
+            let (a, e) = ((actual >> (i * 8)) & 0xFF, (expected >> (i * 8)) & 0xFF);
+            a.abs_diff(e) <= 1
+        });
+        assert!(close, "{:#010X} != {:#010X}", actual, expected);
+    }
+
+    #[test]
+    fn blend_modes_on_opaque_layers() {
+        for (mode, expected) in [
+            (BlendMode::Normal, SOURCE),
+            (BlendMode::Multiply, 0xFF202020),
+            (BlendMode::Screen, 0xFFA0A0A0),
+            (BlendMode::Add, 0xFFC0C0C0),
+            (BlendMode::Overlay, 0xFF414141),
+        ] {
+            assert_close(composite_pixel(BACKDROP, SOURCE, 1.0, mode), expected);
+        }
+        // overlay multiplies dark backdrops and screens bright ones
+        assert_close(
+            composite_pixel(0xFF404040, 0xFF808080, 1.0, BlendMode::Overlay),
+            0xFF404040,
+        );
+    }
+
+    #[test]
+    fn opacity_and_alpha_mix_with_the_backdrop() {
+        assert_close(
+            composite_pixel(BACKDROP, SOURCE, 0.5, BlendMode::Normal),
+            0xFF606060,
+        );
+        assert_close(
+            composite_pixel(BACKDROP, 0x80404040, 1.0, BlendMode::Normal),
+            0xFF606060,
+        );
+        for mode in [BlendMode::Normal, BlendMode::Multiply, BlendMode::Add] {
+            assert_eq!(composite_pixel(BACKDROP, SOURCE, 0.0, mode), BACKDROP);
+            assert_eq!(composite_pixel(BACKDROP, 0x00FFFFFF, 1.0, mode), BACKDROP);
+        }
+    }
+
+    #[test]
+    fn transparent_backdrop_shows_the_plain_source() {
+        for mode in [BlendMode::Multiply, BlendMode::Screen, BlendMode::Overlay] {
+            assert_close(composite_pixel(0x00000000, SOURCE, 1.0, mode), SOURCE);
+            assert_close(composite_pixel(0x00000000, SOURCE, 0.5, mode), 0x7F404040);
+        }
+    }
+}
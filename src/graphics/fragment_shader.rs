@@ -1,6 +1,42 @@
 use crate::graphics::PointLight;
 use crate::graphics::colors::{apply_colored_lighting, clamp_color};
 use crate::vectors::{Vector3d, Vector4d};
+use std::fmt;
+
+// lighting factors shared by both shading modes, so they stay in tune
+pub const AMBIENT: f64 = 0.1; // flat, scaled down per pixel by ambient occlusion
+pub const DIFFUSE: f64 = 0.7;
+pub const SPECULAR: f64 = 0.8;
+pub const SHININESS: i32 = 300;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShadingMode {
+    Phong,
+    Toon { bands: usize },
+}
+
+impl fmt::Display for ShadingMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShadingMode::Phong => write!(f, "Phong"),
+            ShadingMode::Toon { bands } => write!(f, "Toon ({} bands)", bands),
+        }
+    }
+}
+
+pub fn shade_frag(
+    mode: ShadingMode,
+    x: Vector3d,
+    n: Vector3d,
+    v: Vector3d,
+    color: Vector4d,
+    lights: &Vec<PointLight>,
+) -> Vector4d {
+    match mode {
+        ShadingMode::Phong => phong_frag(x, n, v, color, lights),
+        ShadingMode::Toon { bands } => toon_frag(x, n, v, color, lights, bands),
+    }
+}
 
 pub fn phong_frag(
     x: Vector3d,
@@ -19,14 +55,9 @@ pub fn phong_frag(
     */
 
     // Phong
-    let ambient = 0.1;
-    let diffuse_fac = 0.7;
-    let specular_fac = 0.8;
-    let shinyness: i32 = 300;
-
-    let specular_color = clamp_color(color * 2.0);
+    let specular_color = calc_specular_color(color);
 
-    let mut lighting_total = Vector4d::ones() * ambient;
+    let mut lighting_total = Vector4d::ones() * AMBIENT;
 
     for light in lights {
         let l = (light.pos - x).normalize();
@@ -36,16 +67,66 @@ pub fn phong_frag(
         }
 
         // diffuse
-        let l_diff = light.strength * n_dot_l * diffuse_fac;
-        lighting_total += (light.emission * l_diff);
+        let l_diff = light.strength * n_dot_l * DIFFUSE;
+        lighting_total += light.emission * l_diff;
 
         // specular
         let r = n * n_dot_l * 2.0 - l;
         let v_dot_r = v.dot(r);
-        let l_spec = light.strength * v_dot_r.powi(shinyness) * specular_fac;
+        let l_spec = light.strength * v_dot_r.powi(SHININESS) * SPECULAR;
 
         lighting_total += specular_color * l_spec;
     }
 
     return apply_colored_lighting(&color, &lighting_total);
 }
+
+pub fn toon_frag(
+    x: Vector3d,
+    n: Vector3d,
+    v: Vector3d,
+    color: Vector4d,
+    lights: &Vec<PointLight>,
+    bands: usize,
+) -> Vector4d {
+    /*
+        cel shading, same inputs as phong_frag plus
+
+            bands:      number of discrete diffuse levels per light
+
+        the diffuse term is floored onto the band grid, the specular term
+        is either fully on or off
+    */
+    let specular_cutoff = 0.5;
+
+    let bands = bands.max(1) as f64;
+    let specular_color = calc_specular_color(color);
+
+    let mut lighting_total = Vector4d::ones() * AMBIENT;
+
+    for light in lights {
+        let l = (light.pos - x).normalize();
+        let n_dot_l = n.dot(l);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+
+        // quantised diffuse, top band reaches full intensity
+        let band = ((n_dot_l * bands).floor() + 1.0).min(bands) / bands;
+        lighting_total += light.emission * (light.strength * band * DIFFUSE);
+
+        // hard specular
+        let r = n * n_dot_l * 2.0 - l;
+        let v_dot_r = v.dot(r);
+        if v_dot_r > 0.0 && v_dot_r.powi(SHININESS) > specular_cutoff {
+            lighting_total += specular_color * (light.strength * SPECULAR);
+        }
+    }
+
+    apply_colored_lighting(&color, &lighting_total)
+}
+
+fn calc_specular_color(color: Vector4d) -> Vector4d {
+    // highlights are a brightened version of the surface color
+    clamp_color(color * 2.0)
+}
@@ -0,0 +1,60 @@
+use crate::graphics::aabb::Aabb;
+use crate::vectors::matrices::Matrix4x4;
+use crate::vectors::{Vector3d, Vector4d};
+
+/*
+    View frustum as six planes (a, b, c, d), a point p is inside a plane if
+    a * p.x + b * p.y + c * p.z + d >= 0.
+
+    Planes are extracted from the combined projection * camera matrix (Gribb/Hartmann):
+    a point is visible if -w <= x, y, z <= w in clip space, so every plane is
+    the 4th row of the matrix plus or minus one of the first three rows.
+    Planes that degenerate for a given projection matrix (all zero) never cull.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frustum {
+    pub planes: [Vector4d; 6],
+}
+
+impl Frustum {
+    pub fn from_matrix(m: &Matrix4x4) -> Self {
+        let raw = [
+            m.d + m.a, // left
+            m.d - m.a, // right
+            m.d + m.b, // bottom
+            m.d - m.b, // top
+            m.d + m.c, // near
+            m.d - m.c, // far
+        ];
+
+        let mut planes = [Vector4d::zeros(); 6];
+        for (plane, raw_plane) in planes.iter_mut().zip(raw) {
+            let len = raw_plane.truncate_to_3d().length();
+            if len > f64::EPSILON {
+                *plane = raw_plane / len;
+            }
+        }
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, center: &Vector3d, radius: f64) -> bool {
+        /*
+            conservative: false only if the sphere is entirely outside of one plane
+        */
+        for plane in &self.planes {
+            let distance = plane.truncate_to_3d().dot(*center) + plane.u;
+            if distance < -radius {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+        let (center, radius) = aabb.bounding_sphere();
+        self.intersects_sphere(&center, radius)
+    }
+}
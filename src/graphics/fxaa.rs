@@ -0,0 +1,283 @@
+use crate::graphics::colors::{color_vec_from_u32, color_vec_to_u32};
+use crate::graphics::post_effects::PostEffect;
+use crate::util::clamp;
+use crate::vectors::Vector4d;
+
+/*
+    Fast approximate anti-aliasing, after Timothy Lottes' FXAA 3.11 (quality variant).
+
+    Runs on the resolved output image instead of rendering more samples, so it costs about
+    the same at any resolution and reaches interactive framerates with SSAA off. For each
+    pixel with enough local contrast it finds the direction of the edge, walks along it in
+    both directions to its ends and blends the pixel with its neighbour across the edge by
+    how close it is to the nearer end. Thin subpixel features are additionally softened.
+*/
+
+// step sizes when walking along an edge, growing so long edges stay cheap
+const SEARCH_STEPS: [f64; 12] = [1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fxaa {
+    pub edge_threshold: f64, // contrast needed, relative to the brightest neighbour
+    pub edge_threshold_min: f64, // contrast needed in dark areas
+    pub subpixel: f64,       // amount of subpixel softening, [0.0, ... 1.0]
+}
+
+impl Default for Fxaa {
+    fn default() -> Self {
+        Self {
+            edge_threshold: 0.125,
+            edge_threshold_min: 0.0312,
+            subpixel: 0.75,
+        }
+    }
+}
+
+impl PostEffect for Fxaa {
+    fn name(&self) -> &str {
+        "FXAA"
+    }
+
+    fn apply(&self, pixels: &mut [Vector4d], size_x: usize, size_y: usize) {
+        let lumas = LumaPlane::new(pixels.iter().map(luma), size_x, size_y);
+        let source = pixels.to_vec();
+        self.blend_edges(&lumas, |idx, neighbour_idx, blend| {
+            let pixel = &mut pixels[idx];
+            let alpha = pixel.u;
+            *pixel = *pixel * (1.0 - blend) + source[neighbour_idx] * blend;
+            pixel.u = alpha;
+        });
+    }
+}
+
+impl Fxaa {
+    pub fn apply_packed(&self, buffer: &mut [u32], size_x: usize, size_y: usize) {
+        /*
+            same as apply, but on a packed 0xAARRGGBB buffer like Canvas::buffer, so only
+            the pixels on edges are unpacked
+        */
+        let lumas = LumaPlane::new(
+            buffer.iter().map(|val| luma(&color_vec_from_u32(*val))),
+            size_x,
+            size_y,
+        );
+        let source = buffer.to_vec();
+        self.blend_edges(&lumas, |idx, neighbour_idx, blend| {
+            let pixel = color_vec_from_u32(source[idx]);
+            let mut blended =
+                pixel * (1.0 - blend) + color_vec_from_u32(source[neighbour_idx]) * blend;
+            blended.u = pixel.u;
+            buffer[idx] = color_vec_to_u32(&blended);
+        });
+    }
+
+    fn blend_edges(&self, lumas: &LumaPlane, mut blend_pixel: impl FnMut(usize, usize, f64)) {
+        /*
+            finds the edge pixels and calls blend_pixel(pixel, neighbour across the edge, weight)
+            for each of them, both as indices into the unpadded image
+        */
+        let (size_x, size_y) = (lumas.size_x, lumas.size_y);
+        let stride = lumas.stride();
+        let values = &lumas.values;
+        // bilinear lookups between pixel centers, (0.0, 0.0) is the center of the first pixel
+        let sample_luma = |x: f64, y: f64| -> f64 {
+            let x = x.clamp(-1.0, size_x as f64 - 1e-9);
+            let y = y.clamp(-1.0, size_y as f64 - 1e-9);
+            let (x_0, y_0) = (x.floor(), y.floor());
+            let (t_x, t_y) = (x - x_0, y - y_0);
+            let i = lumas.index(x_0 as i64, y_0 as i64);
+            let top = values[i] * (1.0 - t_x) + values[i + 1] * t_x;
+            let bottom = values[i + stride] * (1.0 - t_x) + values[i + stride + 1] * t_x;
+            top * (1.0 - t_y) + bottom * t_y
+        };
+
+        for y in 0..size_y {
+            for x in 0..size_x {
+                let (xi, yi) = (x as i64, y as i64);
+                let i = lumas.index(xi, yi);
+                let luma_m = values[i];
+                let luma_n = values[i - stride];
+                let luma_s = values[i + stride];
+                let luma_w = values[i - 1];
+                let luma_e = values[i + 1];
+
+                let luma_max = luma_m.max(luma_n).max(luma_s).max(luma_w).max(luma_e);
+                let luma_min = luma_m.min(luma_n).min(luma_s).min(luma_w).min(luma_e);
+                let range = luma_max - luma_min;
+                if range < self.edge_threshold_min.max(luma_max * self.edge_threshold) {
+                    continue;
+                }
+
+                let luma_nw = values[i - stride - 1];
+                let luma_ne = values[i - stride + 1];
+                let luma_sw = values[i + stride - 1];
+                let luma_se = values[i + stride + 1];
+                // subpixel softening by the contrast to the 3x3 average
+                let luma_average = (2.0 * (luma_n + luma_s + luma_w + luma_e)
+                    + luma_nw
+                    + luma_ne
+                    + luma_sw
+                    + luma_se)
+                    / 12.0;
+                let subpixel_t = clamp((luma_average - luma_m).abs() / range);
+                let subpixel_t = subpixel_t * subpixel_t * (3.0 - 2.0 * subpixel_t);
+                let subpixel_offset = subpixel_t * subpixel_t * self.subpixel;
+
+                // a horizontal edge has its contrast across rows
+                let edge_horizontal = (luma_nw - 2.0 * luma_w + luma_sw).abs()
+                    + 2.0 * (luma_n - 2.0 * luma_m + luma_s).abs()
+                    + (luma_ne - 2.0 * luma_e + luma_se).abs();
+                let edge_vertical = (luma_nw - 2.0 * luma_n + luma_ne).abs()
+                    + 2.0 * (luma_w - 2.0 * luma_m + luma_e).abs()
+                    + (luma_sw - 2.0 * luma_s + luma_se).abs();
+                let horizontal = edge_horizontal >= edge_vertical;
+
+                // pick the side of the edge with the larger gradient
+                let (luma_1, luma_2) = if horizontal {
+                    (luma_n, luma_s)
+                } else {
+                    (luma_w, luma_e)
+                };
+                let (gradient_1, gradient_2) = (luma_1 - luma_m, luma_2 - luma_m);
+                let first_steepest = gradient_1.abs() >= gradient_2.abs();
+                let gradient_scaled = 0.25 * gradient_1.abs().max(gradient_2.abs());
+                let (step, luma_local_average) = if first_steepest {
+                    (-1.0, 0.5 * (luma_1 + luma_m))
+                } else {
+                    (1.0, 0.5 * (luma_2 + luma_m))
+                };
+
+                // start on the edge between the pixel and its neighbour across it
+                let (mut pos_x, mut pos_y) = (x as f64, y as f64);
+                let (along_x, along_y) = if horizontal {
+                    pos_y += step * 0.5;
+                    (1.0, 0.0)
+                } else {
+                    pos_x += step * 0.5;
+                    (0.0, 1.0)
+                };
+
+                // walk along the edge in both directions until the contrast changes
+                let (mut end_1, mut end_2) = (0.0, 0.0);
+                let (mut luma_end_1, mut luma_end_2) = (0.0, 0.0);
+                let (mut reached_1, mut reached_2) = (false, false);
+                for step_size in SEARCH_STEPS {
+                    if !reached_1 {
+                        end_1 += step_size;
+                        luma_end_1 = sample_luma(pos_x - along_x * end_1, pos_y - along_y * end_1)
+                            - luma_local_average;
+                        reached_1 = luma_end_1.abs() >= gradient_scaled;
+                    }
+                    if !reached_2 {
+                        end_2 += step_size;
+                        luma_end_2 = sample_luma(pos_x + along_x * end_2, pos_y + along_y * end_2)
+                            - luma_local_average;
+                        reached_2 = luma_end_2.abs() >= gradient_scaled;
+                    }
+                    if reached_1 && reached_2 {
+                        break;
+                    }
+                }
+
+                // blend more the closer the pixel is to the end of the edge, if the end fits
+                let (distance, luma_end) = if end_1 < end_2 {
+                    (end_1, luma_end_1)
+                } else {
+                    (end_2, luma_end_2)
+                };
+                let edge_offset = if (luma_end < 0.0) != (luma_m < luma_local_average) {
+                    0.5 - distance / (end_1 + end_2)
+                } else {
+                    0.0
+                };
+                let blend = edge_offset.max(subpixel_offset);
+
+                let (neighbour_x, neighbour_y) = if horizontal {
+                    (xi, yi + step as i64)
+                } else {
+                    (xi + step as i64, yi)
+                };
+                let neighbour_x = neighbour_x.clamp(0, size_x as i64 - 1) as usize;
+                let neighbour_y = neighbour_y.clamp(0, size_y as i64 - 1) as usize;
+                blend_pixel(y * size_x + x, neighbour_y * size_x + neighbour_x, blend);
+            }
+        }
+    }
+}
+
+/*
+    Luma of every pixel with a one pixel border repeating the outermost pixels,
+    so the 3x3 neighbourhood of any pixel can be read without clamping.
+*/
+struct LumaPlane {
+    values: Vec<f64>,
+    size_x: usize,
+    size_y: usize,
+}
+
+impl LumaPlane {
+    fn new(lumas: impl Iterator<Item = f64>, size_x: usize, size_y: usize) -> Self {
+        let stride = size_x + 2;
+        let mut values = vec![0.0; stride * (size_y + 2)];
+        for (i, luma) in lumas.enumerate() {
+            values[(i / size_x + 1) * stride + i % size_x + 1] = luma;
+        }
+        for y in 1..=size_y {
+            values[y * stride] = values[y * stride + 1];
+            values[y * stride + size_x + 1] = values[y * stride + size_x];
+        }
+        values.copy_within(stride..2 * stride, 0);
+        values.copy_within(
+            size_y * stride..(size_y + 1) * stride,
+            (size_y + 1) * stride,
+        );
+        Self {
+            values,
+            size_x,
+            size_y,
+        }
+    }
+
+    fn stride(&self) -> usize {
+        self.size_x + 2
+    }
+
+    fn index(&self, x: i64, y: i64) -> usize {
+        // x and y in -1..=size, the border included
+        ((y + 1) as usize) * self.stride() + (x + 1) as usize
+    }
+}
+
+fn luma(color: &Vector4d) -> f64 {
+    // perceived brightness of a display encoded color
+    0.299 * color.x + 0.587 * color.y + 0.114 * color.z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_and_float_buffers_blend_the_same() {
+        // a hard diagonal edge, the only pixels with contrast are along it
+        let (size_x, size_y) = (12, 9);
+        let packed: Vec<u32> = (0..size_x * size_y)
+            .map(|i| {
+                if i % size_x > i / size_x {
+                    0xFFFFFFFF
+                } else {
+                    0xFF000000
+                }
+            })
+            .collect();
+        let mut pixels: Vec<Vector4d> = packed.iter().map(|val| color_vec_from_u32(*val)).collect();
+        let mut blended = packed.clone();
+
+        Fxaa::default().apply(&mut pixels, size_x, size_y);
+        Fxaa::default().apply_packed(&mut blended, size_x, size_y);
+
+        assert_ne!(blended, packed);
+        let expected: Vec<u32> = pixels.iter().map(color_vec_to_u32).collect();
+        assert_eq!(blended, expected);
+    }
+}
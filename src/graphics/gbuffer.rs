@@ -0,0 +1,60 @@
+use crate::vectors::{Vector3d, Vector4d};
+
+/*
+    Geometry buffer for deferred shading and screen-space effects.
+
+    Holds what lighting needs per supersized pixel, so it can run once per visible pixel after
+    all geometry is drawn instead of for every rasterized fragment. Depth and normal are not
+    duplicated here, they stay in the canvas' z- and normal buffers shared with forward
+    rendering. SSAO reads its positions from here as well.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct GBuffer {
+    pub position: Vec<Vector3d>,      // camera space
+    pub albedo: Vec<Vector4d>,        // linear surface color, straight alpha
+    pub material: Vec<Option<usize>>, // mesh whose material applies, None for implicit shapes
+    pub covered: Vec<bool>,           // a surface was written since the last clear
+}
+
+impl GBuffer {
+    pub fn new(size: usize) -> Self {
+        Self {
+            position: vec![Vector3d::zero(); size],
+            albedo: vec![Vector4d::zeros(); size],
+            material: vec![None; size],
+            covered: vec![false; size],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.position.fill(Vector3d::zero());
+        self.albedo.fill(Vector4d::zeros());
+        self.material.fill(None);
+        self.covered.fill(false);
+    }
+
+    pub fn set(&mut self, idx: usize, surface: &Surface, material: Option<usize>) {
+        self.position[idx] = surface.position;
+        self.albedo[idx] = surface.albedo;
+        self.material[idx] = material;
+        self.covered[idx] = true;
+    }
+}
+
+// unlit surface attributes of a fragment, in camera space
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Surface {
+    pub position: Vector3d,
+    pub normal: Vector3d,
+    pub albedo: Vector4d,
+}
+
+impl Surface {
+    pub fn new(position: Vector3d, normal: Vector3d, albedo: Vector4d) -> Self {
+        Self {
+            position,
+            normal,
+            albedo,
+        }
+    }
+}
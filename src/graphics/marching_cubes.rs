@@ -0,0 +1,402 @@
+use crate::error::RenderError;
+use crate::graphics::aabb::Aabb;
+use crate::graphics::sdf::Sdf;
+use crate::graphics::shapes::Mesh;
+use crate::vectors::{Vector3d, Vector4d};
+use std::collections::HashMap;
+
+/*
+    Marching cubes, turns a scalar field into a triangle mesh of its iso surface.
+
+    The field is sampled on a regular grid over the given bounds, points with a value
+    below iso count as inside (like the inside of an Sdf). Every grid cell emits the
+    triangles of its case, vertices sit on the cell edges where the field crosses iso
+    and are shared between neighbouring cells.
+
+    Instead of the usual hand written 256 case table, the triangles of each case are
+    derived once by walking the faces of the cube: on every face the crossed edges are
+    connected into segments around the inside corners, the segments of all faces close
+    into loops and every loop is fanned into triangles. Ambiguous faces always keep
+    their inside corners apart, so neighbouring cells agree and the surface stays closed.
+    The fan starts at a loop vertex that keeps every triangle off the cube faces, a
+    triangle lying flat in a face would be emitted by the neighbouring cell as well.
+*/
+
+// corners of a cell as offsets from its lowest corner
+const CORNERS: [(usize, usize, usize); 8] = [
+    (0, 0, 0),
+    (1, 0, 0),
+    (1, 1, 0),
+    (0, 1, 0),
+    (0, 0, 1),
+    (1, 0, 1),
+    (1, 1, 1),
+    (0, 1, 1),
+];
+
+// edges as pairs of corners
+const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (1, 2),
+    (3, 2),
+    (0, 3),
+    (4, 5),
+    (5, 6),
+    (7, 6),
+    (4, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+// faces as corner loops, counter clockwise seen from outside of the cell
+const FACES: [[usize; 4]; 6] = [
+    [0, 3, 2, 1], // z = 0
+    [4, 5, 6, 7], // z = 1
+    [0, 1, 5, 4], // y = 0
+    [3, 7, 6, 2], // y = 1
+    [0, 4, 7, 3], // x = 0
+    [1, 2, 6, 5], // x = 1
+];
+
+fn edge_between(a: usize, b: usize) -> usize {
+    EDGES
+        .iter()
+        .position(|&(e_a, e_b)| (e_a, e_b) == (a, b) || (e_a, e_b) == (b, a))
+        .expect("corners of a face are always connected by an edge")
+}
+
+fn lies_in_face(triangle: [usize; 3]) -> bool {
+    // all three edges on one face of the cell, only possible on faces with four crossed edges
+    FACES.iter().any(|face| {
+        triangle.iter().all(|&edge| {
+            let (a, b) = EDGES[edge];
+            face.contains(&a) && face.contains(&b)
+        })
+    })
+}
+
+fn calc_case_table() -> Vec<Vec<[usize; 3]>> {
+    /*
+        triangles (as edge indices) for each of the 256 inside/outside configurations,
+        bit i of the configuration is set if corner i is inside
+    */
+    let mut table = Vec::with_capacity(256);
+    for config in 0..256usize {
+        let inside = |corner: usize| config & (1 << corner) != 0;
+
+        // on each face, connect every edge entering the inside to the next edge leaving it
+        let mut next_edge: [Option<usize>; 12] = [None; 12];
+        for face in FACES {
+            let mut entering = None;
+            let mut pending_exit = None;
+            for i in 0..4 {
+                let (a, b) = (face[i], face[(i + 1) % 4]);
+                if inside(a) == inside(b) {
+                    continue;
+                }
+                let edge = edge_between(a, b);
+                if inside(b) {
+                    entering = Some(edge);
+                } else if let Some(enter) = entering.take() {
+                    next_edge[enter] = Some(edge);
+                } else {
+                    // the walk started inside, this exit belongs to the last entry
+                    pending_exit = Some(edge);
+                }
+            }
+            if let (Some(enter), Some(exit)) = (entering, pending_exit) {
+                next_edge[enter] = Some(exit);
+            }
+        }
+
+        // follow the segments into closed loops and fan them into triangles
+        let mut triangles = vec![];
+        let mut visited = [false; 12];
+        for start in 0..12 {
+            if visited[start] || next_edge[start].is_none() {
+                continue;
+            }
+            let mut polygon = vec![];
+            let mut edge = start;
+            while !visited[edge] {
+                visited[edge] = true;
+                polygon.push(edge);
+                match next_edge[edge] {
+                    Some(next) => edge = next,
+                    None => break,
+                }
+            }
+            let fan = |start: usize| -> Vec<[usize; 3]> {
+                let vertex = |i: usize| polygon[(start + i) % polygon.len()];
+                (1..polygon.len().saturating_sub(1))
+                    .map(|i| [vertex(0), vertex(i), vertex(i + 1)])
+                    .collect()
+            };
+            let start = (0..polygon.len())
+                .find(|&start| !fan(start).into_iter().any(lies_in_face))
+                .expect("every loop of the 256 cases has a fan that stays off the cube faces");
+            triangles.extend(fan(start));
+        }
+        table.push(triangles);
+    }
+    table
+}
+
+pub fn calc_isosurface_from_volume(
+    values: &[f64],
+    dims: (usize, usize, usize),
+    bounds: &Aabb,
+    iso: f64,
+    color: &Vector4d,
+) -> Result<Mesh, RenderError> {
+    /*
+        meshes sampled volume data, values[x + y * dims.0 + z * dims.0 * dims.1] lies at
+        the grid point (x, y, z) spanning bounds. vertex normals come from the faces
+    */
+    let (nx, ny, nz) = dims;
+    if nx < 2 || ny < 2 || nz < 2 {
+        return Err(RenderError::InvalidResolution(format!(
+            "marching cubes needs at least 2 samples per axis, found {}x{}x{}",
+            nx, ny, nz
+        )));
+    }
+    if values.len() != nx * ny * nz {
+        return Err(RenderError::InvalidArgument(format!(
+            "volume has {} values, expected {}x{}x{}",
+            values.len(),
+            nx,
+            ny,
+            nz
+        )));
+    }
+    if bounds.is_empty() {
+        return Err(RenderError::InvalidArgument(
+            "marching cubes bounds must not be empty".to_string(),
+        ));
+    }
+
+    let extent = bounds.extent();
+    let cell_size = Vector3d::new(
+        extent.x / (nx - 1) as f64,
+        extent.y / (ny - 1) as f64,
+        extent.z / (nz - 1) as f64,
+    );
+    let grid_point = |x: usize, y: usize, z: usize| -> Vector3d {
+        bounds.min
+            + Vector3d::new(
+                x as f64 * cell_size.x,
+                y as f64 * cell_size.y,
+                z as f64 * cell_size.z,
+            )
+    };
+    let value = |x: usize, y: usize, z: usize| values[x + y * nx + z * nx * ny];
+
+    let case_table = calc_case_table();
+    let mut vertices = vec![];
+    let mut faces = vec![];
+    // vertex index per grid edge, keyed by its lower grid point and axis
+    let mut edge_vertices: HashMap<(usize, usize, usize, usize), usize> = HashMap::new();
+
+    for z in 0..nz - 1 {
+        for y in 0..ny - 1 {
+            for x in 0..nx - 1 {
+                let corner_pos = |corner: usize| {
+                    let (dx, dy, dz) = CORNERS[corner];
+                    (x + dx, y + dy, z + dz)
+                };
+                let corner_value = |corner: usize| {
+                    let (cx, cy, cz) = corner_pos(corner);
+                    value(cx, cy, cz)
+                };
+
+                let mut config = 0;
+                for corner in 0..8 {
+                    if corner_value(corner) < iso {
+                        config |= 1 << corner;
+                    }
+                }
+                if config == 0 || config == 255 {
+                    continue;
+                }
+
+                for triangle in &case_table[config] {
+                    let mut indices = [0; 3];
+                    // points from inside to outside, the direction the surface should face
+                    let mut outward = Vector3d::zero();
+                    for (i, &edge) in triangle.iter().enumerate() {
+                        let (a, b) = EDGES[edge];
+                        let (pos_a, pos_b) = (corner_pos(a), corner_pos(b));
+                        let (p_a, p_b) = (
+                            grid_point(pos_a.0, pos_a.1, pos_a.2),
+                            grid_point(pos_b.0, pos_b.1, pos_b.2),
+                        );
+                        let (v_a, v_b) = (corner_value(a), corner_value(b));
+                        outward += if v_a < iso { p_b - p_a } else { p_a - p_b };
+
+                        let lower = pos_a.min(pos_b);
+                        let axis = if pos_a.0 != pos_b.0 {
+                            0
+                        } else if pos_a.1 != pos_b.1 {
+                            1
+                        } else {
+                            2
+                        };
+                        indices[i] = *edge_vertices
+                            .entry((lower.0, lower.1, lower.2, axis))
+                            .or_insert_with(|| {
+                                let t = ((iso - v_a) / (v_b - v_a)).clamp(0.0, 1.0);
+                                vertices.push(p_a + (p_b - p_a) * t);
+                                vertices.len() - 1
+                            });
+                    }
+
+                    let (p1, p2, p3) = (
+                        vertices[indices[0]],
+                        vertices[indices[1]],
+                        vertices[indices[2]],
+                    );
+                    if (p2 - p1).cross(p3 - p1).dot(outward) < 0.0 {
+                        indices.swap(1, 2);
+                    }
+                    faces.push(indices.to_vec());
+                }
+            }
+        }
+    }
+
+    let mut mesh = Mesh::new(
+        vertices,
+        faces,
+        *color,
+        cell_size.x.min(cell_size.y).min(cell_size.z) * 1e-3,
+    );
+    mesh.recalc_vertex_normals();
+    Ok(mesh)
+}
+
+pub fn calc_isosurface(
+    field: impl Fn(Vector3d) -> f64,
+    bounds: &Aabb,
+    resolution: usize,
+    iso: f64,
+    color: &Vector4d,
+) -> Result<Mesh, RenderError> {
+    /*
+        meshes the surface field(p) == iso within bounds, using resolution cells per axis.
+        vertex normals are the field's gradient by central differences
+    */
+    if resolution < 1 {
+        return Err(RenderError::InvalidResolution(
+            "marching cubes needs a resolution of at least 1".to_string(),
+        ));
+    }
+    let samples = resolution + 1;
+    let extent = bounds.extent();
+    let mut values = Vec::with_capacity(samples * samples * samples);
+    for z in 0..samples {
+        for y in 0..samples {
+            for x in 0..samples {
+                values.push(field(
+                    bounds.min
+                        + Vector3d::new(
+                            extent.x * x as f64 / resolution as f64,
+                            extent.y * y as f64 / resolution as f64,
+                            extent.z * z as f64 / resolution as f64,
+                        ),
+                ));
+            }
+        }
+    }
+    let mut mesh =
+        calc_isosurface_from_volume(&values, (samples, samples, samples), bounds, iso, color)?;
+
+    let h = extent.x.min(extent.y).min(extent.z) / resolution as f64 * 1e-2;
+    let (dx, dy, dz) = (
+        Vector3d::new(h, 0.0, 0.0),
+        Vector3d::new(0.0, h, 0.0),
+        Vector3d::new(0.0, 0.0, h),
+    );
+    mesh.vertex_normals = mesh
+        .vertices
+        .iter()
+        .zip(&mesh.vertex_normals)
+        .map(|(&p, face_normal)| {
+            let gradient = Vector3d::new(
+                field(p + dx) - field(p - dx),
+                field(p + dy) - field(p - dy),
+                field(p + dz) - field(p - dz),
+            )
+            .normalize();
+            // flat spots of the field have no gradient, keep the face based normal there
+            if gradient.length() > 0.0 {
+                gradient
+            } else {
+                *face_normal
+            }
+        })
+        .collect();
+    Ok(mesh)
+}
+
+pub fn calc_sdf_mesh(
+    sdf: &Sdf,
+    bounds: &Aabb,
+    resolution: usize,
+    color: &Vector4d,
+) -> Result<Mesh, RenderError> {
+    calc_isosurface(|p| sdf.distance(p), bounds, resolution, 0.0, color)
+}
+
+pub fn metaballs(balls: Vec<(Vector3d, f64)>) -> impl Fn(Vector3d) -> f64 {
+    /*
+        field of metaballs given as (center, radius), negative inside like an Sdf,
+        so the surface is at iso 0.0
+    */
+    move |p| {
+        let sum: f64 = balls
+            .iter()
+            .map(|(center, radius)| {
+                radius * radius / (p - *center).dot(p - *center).max(f64::EPSILON)
+            })
+            .sum();
+        1.0 - sum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_table_covers_all_configurations() {
+        // building the table runs the fan search for every loop of every case
+        let table = calc_case_table();
+        assert_eq!(table.len(), 256);
+        for (config, triangles) in table.iter().enumerate() {
+            assert_eq!(triangles.is_empty(), config == 0 || config == 255);
+            assert!(!triangles.iter().any(|&triangle| lies_in_face(triangle)));
+        }
+    }
+
+    #[test]
+    fn sphere_mesh_is_closed() {
+        let bounds = Aabb::new(
+            Vector3d::new(-1.3, -1.2, -1.1),
+            Vector3d::new(1.1, 1.2, 1.3),
+        );
+        let sdf = Sdf::sphere(Vector3d::new(0.05, -0.03, 0.02), 1.0);
+        let mesh = calc_sdf_mesh(&sdf, &bounds, 17, &Vector4d::new(1.0, 1.0, 1.0, 1.0)).unwrap();
+        assert!(!mesh.faces.is_empty());
+
+        let mut edge_count: HashMap<(usize, usize), usize> = HashMap::new();
+        for face in &mesh.faces {
+            for i in 0..3 {
+                let (a, b) = (face[i], face[(i + 1) % 3]);
+                *edge_count.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+        }
+        assert!(edge_count.values().all(|&count| count == 2));
+    }
+}
@@ -0,0 +1,80 @@
+use crate::vectors::Vector3d;
+use std::fmt;
+
+/*
+    Surface parameters of a mesh that go beyond its color. The rasterizer ignores them,
+    the ray tracer splits the light leaving a surface into a local (shaded), a mirrored
+    and a refracted share. The path tracer picks one of these lobes at random per bounce
+    with the same shares, the local one being Lambertian, and blurs mirror reflections by roughness.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Material {
+    pub reflectivity: f64, // share of mirror reflection, [0.0, ... 1.0]
+    pub transparency: f64, // share of refracted light, reflectivity + transparency <= 1.0
+    pub ior: f64,          // index of refraction, 1.0 for air
+
+    pub roughness: f64, // 0.0 for a perfect mirror, up to 1.0 for a diffuse-like gloss
+    pub emission: Vector3d, // emitted light, rgb, may exceed 1.0
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::diffuse()
+    }
+}
+
+impl Material {
+    pub fn new(reflectivity: f64, transparency: f64, ior: f64) -> Self {
+        Self {
+            reflectivity,
+            transparency,
+            ior,
+            roughness: 0.0,
+            emission: Vector3d::zero(),
+        }
+    }
+
+    pub fn diffuse() -> Self {
+        Self::new(0.0, 0.0, 1.0)
+    }
+
+    pub fn mirror(reflectivity: f64) -> Self {
+        Self::new(reflectivity, 0.0, 1.0)
+    }
+
+    pub fn glass(ior: f64) -> Self {
+        Self::new(0.1, 0.9, ior)
+    }
+
+    pub fn glossy(reflectivity: f64, roughness: f64) -> Self {
+        Self {
+            roughness,
+            ..Self::mirror(reflectivity)
+        }
+    }
+
+    pub fn emissive(emission: Vector3d) -> Self {
+        Self {
+            emission,
+            ..Self::diffuse()
+        }
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission.x > 0.0 || self.emission.y > 0.0 || self.emission.z > 0.0
+    }
+
+    pub fn local_share(&self) -> f64 {
+        (1.0 - self.reflectivity - self.transparency).max(0.0)
+    }
+}
+
+impl fmt::Display for Material {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Material (reflectivity {}, transparency {}, ior {}, roughness {}, emission {})",
+            self.reflectivity, self.transparency, self.ior, self.roughness, self.emission
+        )
+    }
+}
@@ -0,0 +1,75 @@
+use crate::error::RenderError;
+use crate::graphics::shapes::Mesh;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/*
+    Writes meshes to common interchange formats, e.g. to keep the output of the
+    marching cubes mesher. Vertices are exported in world space, so the mesh's
+    transform is baked in.
+*/
+
+pub fn save_obj(mesh: &Mesh, path: &str) -> Result<(), RenderError> {
+    /*
+        Wavefront OBJ with positions, vertex normals (if calculated) and triangle faces
+    */
+    let mut file = BufWriter::new(File::create(path)?);
+    for v in mesh.world_vertices() {
+        writeln!(file, "v {} {} {}", v.x, v.y, v.z)?;
+    }
+    let with_normals = mesh.vertex_normals.len() == mesh.vertices.len();
+    if with_normals {
+        for normal in &mesh.vertex_normals {
+            let n = mesh.world_normal(normal);
+            writeln!(file, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+    }
+    for face in &mesh.faces {
+        // indices start at 1
+        let indices: Vec<String> = face
+            .iter()
+            .map(|idx| {
+                if with_normals {
+                    format!("{0}//{0}", idx + 1)
+                } else {
+                    (idx + 1).to_string()
+                }
+            })
+            .collect();
+        writeln!(file, "f {}", indices.join(" "))?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+pub fn save_stl(mesh: &Mesh, path: &str) -> Result<(), RenderError> {
+    /*
+        binary STL: 80 byte header, triangle count, then per triangle the face normal,
+        three corners and an unused attribute, all little endian
+    */
+    let face_count = u32::try_from(mesh.faces.len()).map_err(|_| {
+        RenderError::InvalidArgument(format!(
+            "{} faces do not fit into an STL file",
+            mesh.faces.len()
+        ))
+    })?;
+    let vertices = mesh.world_vertices();
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut header = [0u8; 80];
+    let title = b"rusty-ruling-pen";
+    header[..title.len()].copy_from_slice(title);
+    file.write_all(&header)?;
+    file.write_all(&face_count.to_le_bytes())?;
+    for face in &mesh.faces {
+        let (p1, p2, p3) = (vertices[face[0]], vertices[face[1]], vertices[face[2]]);
+        let normal = (p2 - p1).cross(p3 - p1).normalize();
+        for v in [normal, p1, p2, p3] {
+            for c in [v.x, v.y, v.z] {
+                file.write_all(&(c as f32).to_le_bytes())?;
+            }
+        }
+        file.write_all(&0u16.to_le_bytes())?;
+    }
+    file.flush()?;
+    Ok(())
+}
@@ -1,16 +1,63 @@
+pub mod aabb;
+pub mod aov;
+pub mod bvh;
 mod camera;
 pub mod canvas;
 pub mod colors;
+pub mod compositing;
 mod fragment_shader;
+pub mod frustum;
+pub mod fxaa;
+pub mod gbuffer;
 mod lighting;
+pub mod marching_cubes;
+pub mod material;
+pub mod mesh_export;
+pub mod msaa;
+pub mod object_id;
+pub mod outline;
+pub mod path_tracer;
+pub mod post_effects;
+pub mod ray;
+pub mod ray_tracer;
+pub mod resampling;
 pub mod scanline;
+pub mod sdf;
 mod shapes;
+pub mod ssao;
+pub mod tone_mapping;
+pub mod transparency;
 pub mod triangles;
 
 // Re-export so callers can write `sprites::Sprite` directly
+pub use aabb::Aabb;
+pub use aov::{Aov, AovFormat};
+pub use bvh::{Bvh, BvhNode, BvhPrimitive};
 pub use camera::Camera;
-pub use canvas::{Canvas, SSAA};
+pub use canvas::{Canvas, Renderer, SSAA};
 pub use colors::alpha_blend;
+pub use compositing::BlendMode;
+pub use fragment_shader::ShadingMode;
+pub use frustum::Frustum;
+pub use fxaa::Fxaa;
+pub use gbuffer::{GBuffer, Surface};
 pub use lighting::PointLight;
-pub use shapes::{calc_cube, calc_sphere, calc_teapot, calc_torus};
+pub use marching_cubes::{calc_isosurface, calc_isosurface_from_volume, calc_sdf_mesh, metaballs};
+pub use material::Material;
+pub use mesh_export::{save_obj, save_stl};
+pub use msaa::Msaa;
+pub use object_id::ObjectId;
+pub use outline::Outline;
+pub use path_tracer::PathTracer;
+pub use post_effects::{
+    Bloom, ChromaticAberration, ColorGrading, FilmGrain, Lut3d, PostEffect, Sharpen, Vignette,
+    parse_post_effects,
+};
+pub use ray::{Ray, RayHit};
+pub use ray_tracer::RayTracer;
+pub use resampling::{ReconstructionFilter, UpscaleFilter};
+pub use sdf::{Sdf, SdfShape};
+pub use shapes::{Mesh, MeshBuilder, Scene, calc_cube, calc_sphere, calc_teapot, calc_torus};
+pub use ssao::Ssao;
+pub use tone_mapping::ToneMapping;
 pub use triangles::Triangle3d;
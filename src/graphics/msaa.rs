@@ -0,0 +1,238 @@
+use crate::error::RenderError;
+use std::fmt;
+
+/*
+    Multisample anti-aliasing for the rasterizer.
+
+    Instead of rendering everything at a higher resolution (SSAA), coverage and depth of each
+    triangle are tested at several sample positions inside a (supersized) pixel, but the
+    fragment shader runs only once per pixel and triangle, at the centroid of the covered
+    samples. Its color is stored for every covered sample that passes the depth test and the
+    samples are averaged before the rest of the pipeline (implicit shapes, transparency,
+    SSAA resolve) continues per pixel. Edges get as smooth as with SSAA at the same sample
+    count while shading, the expensive part, stays at one evaluation per pixel.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Msaa {
+    pub name: String,
+    samples: Vec<(f64, f64)>, // offsets from the pixel center, within [-0.5, ... 0.5], never empty
+}
+
+impl Msaa {
+    pub fn custom(name: &str, samples: Vec<(f64, f64)>) -> Result<Self, RenderError> {
+        // a pattern needs at least one sample and every sample has to lie inside the pixel
+        if samples.is_empty() {
+            return Err(RenderError::InvalidArgument(format!(
+                "MSAA pattern \"{}\" has no samples",
+                name
+            )));
+        }
+        if let Some((x, y)) = samples
+            .iter()
+            .find(|(x, y)| !(-0.5..=0.5).contains(x) || !(-0.5..=0.5).contains(y))
+        {
+            return Err(RenderError::InvalidArgument(format!(
+                "MSAA sample ({}, {}) of \"{}\" lies outside of the pixel",
+                x, y, name
+            )));
+        }
+        Ok(Self::from_valid_samples(name, samples))
+    }
+
+    fn from_valid_samples(name: &str, samples: Vec<(f64, f64)>) -> Self {
+        Self {
+            name: name.to_string(),
+            samples,
+        }
+    }
+
+    pub fn ordered_grid(n: usize) -> Self {
+        // n x n samples on a regular grid, like SSAA with n * n samples per pixel
+        let n = n.max(1);
+        let step = 1.0 / n as f64;
+        let samples = (0..n * n)
+            .map(|i| {
+                (
+                    ((i % n) as f64 + 0.5) * step - 0.5,
+                    ((i / n) as f64 + 0.5) * step - 0.5,
+                )
+            })
+            .collect();
+        Self::from_valid_samples(&format!("{}x ordered grid", n * n), samples)
+    }
+
+    pub fn rotated_grid() -> Self {
+        /*
+            4 samples on a rotated grid, the standard 4x pattern. no two samples share
+            a row or column, so near horizontal and vertical edges get 4 coverage steps
+        */
+        Self::from_valid_samples(
+            "4x rotated grid",
+            [(-2.0, -6.0), (6.0, -2.0), (-6.0, 2.0), (2.0, 6.0)]
+                .map(|(x, y)| (x / 16.0, y / 16.0))
+                .to_vec(),
+        )
+    }
+
+    pub fn sparse_8() -> Self {
+        // the standard 8x pattern, 8 queens style, every row and column used once
+        Self::from_valid_samples(
+            "8x sparse",
+            [
+                (1.0, -3.0),
+                (-1.0, 3.0),
+                (5.0, 1.0),
+                (-3.0, -5.0),
+                (-5.0, 5.0),
+                (-7.0, -1.0),
+                (3.0, 7.0),
+                (7.0, -7.0),
+            ]
+            .map(|(x, y)| (x / 16.0, y / 16.0))
+            .to_vec(),
+        )
+    }
+
+    pub fn samples(&self) -> &[(f64, f64)] {
+        &self.samples
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.samples.len()
+    }
+}
+
+impl fmt::Display for Msaa {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MSAA, {}", self.name)
+    }
+}
+
+pub fn signed_area(points: &[(f64, f64); 3]) -> f64 {
+    // twice the area, positive if the corners are counterclockwise in raster space (front facing)
+    (points[1].0 - points[0].0) * (points[2].1 - points[0].1)
+        - (points[1].1 - points[0].1) * (points[2].0 - points[0].0)
+}
+
+/*
+    Barycentric weights of the three corners and the depth of a triangle, each as a plane
+    a * x + b * y + c in raster space. All weights are >= 0.0 inside the triangle.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TrianglePlanes {
+    planes: [(f64, f64, f64); 4], // weight 1, weight 2, weight 3, depth
+}
+
+impl TrianglePlanes {
+    pub fn new(points: &[(f64, f64); 3], depths: [f64; 3], area: f64) -> Self {
+        // area as returned by signed_area, must not be zero
+        let edge = |a: (f64, f64), b: (f64, f64)| {
+            (
+                (a.1 - b.1) / area,
+                (b.0 - a.0) / area,
+                ((b.1 - a.1) * a.0 - (b.0 - a.0) * a.1) / area,
+            )
+        };
+        let weights = [
+            edge(points[1], points[2]),
+            edge(points[2], points[0]),
+            edge(points[0], points[1]),
+        ];
+        let depth = weights
+            .iter()
+            .zip(depths)
+            .fold((0.0, 0.0, 0.0), |(a, b, c), (w, z)| {
+                (a + w.0 * z, b + w.1 * z, c + w.2 * z)
+            });
+        Self {
+            planes: [weights[0], weights[1], weights[2], depth],
+        }
+    }
+
+    pub fn at(&self, p: (f64, f64)) -> [f64; 4] {
+        self.planes.map(|(a, b, c)| a * p.0 + b * p.1 + c)
+    }
+
+    pub fn step_x(&self, values: &mut [f64; 4]) {
+        // moves values returned by at one pixel to the right
+        for (value, (a, _, _)) in values.iter_mut().zip(self.planes) {
+            *value += a;
+        }
+    }
+
+    pub fn row_span(&self, y: usize, size_x: usize) -> Option<(usize, usize)> {
+        /*
+            pixels of row y (the band y..y + 1) that may hold a sample inside the triangle,
+            as a range x_lower..x_upper. one pixel of slack on each side for samples on the
+            pixel border
+        */
+        let (mut lower, mut upper) = (f64::MIN, f64::MAX);
+        for (a, b, c) in &self.planes[..3] {
+            let edge_at = |y: f64| -(b * y + c) / a;
+            let (y_1, y_2) = (y as f64, y as f64 + 1.0);
+            if *a > 0.0 {
+                lower = lower.max(edge_at(y_1).min(edge_at(y_2)));
+            } else if *a < 0.0 {
+                upper = upper.min(edge_at(y_1).max(edge_at(y_2)));
+            } else if (b * y_1 + c).max(b * y_2 + c) < 0.0 {
+                return None;
+            }
+        }
+        if lower > upper {
+            return None;
+        }
+        let x_lower = (lower.floor() - 1.0).max(0.0) as usize;
+        let x_upper = ((upper.floor() + 2.0).max(0.0) as usize).min(size_x);
+        (x_lower < x_upper).then_some((x_lower, x_upper))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_rejects_empty_and_outside_patterns() {
+        assert!(Msaa::custom("empty", vec![]).is_err());
+        assert!(Msaa::custom("outside", vec![(0.0, 0.0), (0.6, 0.0)]).is_err());
+        assert_eq!(
+            Msaa::custom("corners", vec![(-0.5, -0.5), (0.5, 0.5)])
+                .unwrap()
+                .sample_count(),
+            2
+        );
+    }
+
+    #[test]
+    fn triangle_planes_step_and_span_cover_the_triangle() {
+        let points = [(1.3, 0.7), (9.6, 2.2), (4.1, 8.9)];
+        let planes = TrianglePlanes::new(&points, [0.1, 0.5, 0.9], signed_area(&points));
+
+        // weights are 1.0 at their own corner and the depth interpolates the corner depths
+        let corner = planes.at(points[1]);
+        assert!((corner[1] - 1.0).abs() < 1e-9 && corner[0].abs() < 1e-9);
+        assert!((corner[3] - 0.5).abs() < 1e-9);
+
+        let mut stepped = planes.at((2.5, 4.5));
+        planes.step_x(&mut stepped);
+        let direct = planes.at((3.5, 4.5));
+        assert!(
+            stepped
+                .iter()
+                .zip(direct)
+                .all(|(a, b)| (a - b).abs() < 1e-9)
+        );
+
+        // every pixel center inside the triangle lies within the span of its row
+        for y in 0..10 {
+            for x in 0..12 {
+                let weights = planes.at((x as f64 + 0.5, y as f64 + 0.5));
+                if weights[..3].iter().all(|w| *w >= 0.0) {
+                    let (x_lower, x_upper) = planes.row_span(y, 12).unwrap();
+                    assert!((x_lower..x_upper).contains(&x));
+                }
+            }
+        }
+        assert_eq!(planes.row_span(9, 12), None);
+    }
+}
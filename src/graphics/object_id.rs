@@ -0,0 +1,74 @@
+use crate::error::RenderError;
+use image::GrayImage;
+use std::fmt;
+
+/*
+    Identifies the mesh (index into Scene::meshes) and triangle (index into Mesh::faces)
+    that produced a pixel. The optional ID buffer of the canvas stores one per supersized
+    pixel, None where only background was drawn.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ObjectId {
+    pub mesh: usize,
+    pub face: usize,
+}
+
+impl ObjectId {
+    pub fn new(mesh: usize, face: usize) -> Self {
+        Self { mesh, face }
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "mesh {}, face {}", self.mesh, self.face)
+    }
+}
+
+pub fn detect_mesh_edges(
+    id_buffer: &[Option<ObjectId>],
+    mesh: usize,
+    size_x: usize,
+    size_y: usize,
+) -> Vec<bool> {
+    /*
+        marks every pixel whose right or lower neighbour lies across the border of the mesh,
+        returns a mask in buffer layout
+    */
+    let in_mesh = |idx: usize| id_buffer[idx].is_some_and(|id| id.mesh == mesh);
+
+    let mut edges = vec![false; size_x * size_y];
+    for y in 0..size_y {
+        for x in 0..size_x {
+            let idx = y * size_x + x;
+            if x + 1 < size_x && in_mesh(idx) != in_mesh(idx + 1) {
+                edges[idx] = true;
+            }
+            if y + 1 < size_y && in_mesh(idx) != in_mesh(idx + size_x) {
+                edges[idx] = true;
+            }
+        }
+    }
+    edges
+}
+
+pub fn save_mask(mask: &[u8], size_x: usize, size_y: usize, path: &str) -> Result<(), RenderError> {
+    /*
+        writes a coverage mask (0 = not covered, 255 = fully covered) as grayscale image,
+        the format follows the file extension
+    */
+    if mask.len() != size_x * size_y {
+        return Err(RenderError::InvalidArgument(format!(
+            "mask has {} pixels, expected {}x{}",
+            mask.len(),
+            size_x,
+            size_y
+        )));
+    }
+    let image =
+        GrayImage::from_raw(size_x as u32, size_y as u32, mask.to_vec()).ok_or_else(|| {
+            RenderError::InvalidArgument(format!("mask size {}x{} is too large", size_x, size_y))
+        })?;
+    image.save(path)?;
+    Ok(())
+}
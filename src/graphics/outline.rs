@@ -0,0 +1,103 @@
+use crate::vectors::{Vector3d, Vector4d};
+
+/*
+    Outline pass for cel shading.
+
+    Edges are detected on the supersized z- and normal buffers, then mapped down to
+    output pixels and widened there, so the outline width does not depend on the SSAA factor.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Outline {
+    pub width: usize, // in output pixels
+    pub color: Vector4d,
+
+    pub depth_threshold: f64,  // relative depth jump counted as an edge
+    pub normal_threshold: f64, // 1 - cos(angle) between neighbouring normals counted as an edge
+}
+
+impl Outline {
+    pub fn new(width: usize, color: Vector4d) -> Self {
+        Self {
+            width,
+            color,
+            depth_threshold: 0.05,
+            normal_threshold: 0.3,
+        }
+    }
+
+    fn is_discontinuity(&self, z_1: f64, z_2: f64, n_1: Vector3d, n_2: Vector3d) -> bool {
+        let empty_1 = z_1 == f64::MAX;
+        let empty_2 = z_2 == f64::MAX;
+        if empty_1 || empty_2 {
+            // silhouette against the background
+            return empty_1 != empty_2;
+        }
+
+        let depth_scale = z_1.abs().min(z_2.abs()).max(f64::EPSILON);
+        if (z_1 - z_2).abs() / depth_scale > self.depth_threshold {
+            return true;
+        }
+
+        1.0 - n_1.dot(n_2) > self.normal_threshold
+    }
+
+    pub fn detect_edges(
+        &self,
+        z_buffer: &[f64],
+        normal_buffer: &[Vector3d],
+        size_x: usize,
+        size_y: usize,
+    ) -> Vec<bool> {
+        /*
+            marks every pixel whose right or lower neighbour lies across a discontinuity,
+            returns a mask in buffer layout
+        */
+        let mut edges = vec![false; size_x * size_y];
+        for y in 0..size_y {
+            for x in 0..size_x {
+                let idx = y * size_x + x;
+                let right = (x + 1 < size_x).then_some(idx + 1);
+                let below = (y + 1 < size_y).then_some(idx + size_x);
+                for other in [right, below].into_iter().flatten() {
+                    if self.is_discontinuity(
+                        z_buffer[idx],
+                        z_buffer[other],
+                        normal_buffer[idx],
+                        normal_buffer[other],
+                    ) {
+                        edges[idx] = true;
+                    }
+                }
+            }
+        }
+        edges
+    }
+
+    pub fn widen(&self, edges: &[bool], size_x: usize, size_y: usize) -> Vec<bool> {
+        /*
+            grows every edge pixel into a width x width square
+        */
+        if self.width <= 1 {
+            return edges.to_vec();
+        }
+        let reach_lower = (self.width - 1) / 2;
+        let reach_upper = self.width / 2;
+
+        let mut widened = vec![false; size_x * size_y];
+        for y in 0..size_y {
+            for x in 0..size_x {
+                if !edges[y * size_x + x] {
+                    continue;
+                }
+                let y_range = y.saturating_sub(reach_lower)..(y + reach_upper + 1).min(size_y);
+                for y_ in y_range {
+                    let x_range = x.saturating_sub(reach_lower)..(x + reach_upper + 1).min(size_x);
+                    for x_ in x_range {
+                        widened[y_ * size_x + x_] = true;
+                    }
+                }
+            }
+        }
+        widened
+    }
+}
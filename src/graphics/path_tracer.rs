@@ -0,0 +1,288 @@
+use crate::graphics::canvas::{Canvas, RenderStats};
+use crate::graphics::colors::premultiply;
+use crate::graphics::gbuffer::Surface;
+use crate::graphics::object_id::ObjectId;
+use crate::graphics::ray::Ray;
+use crate::graphics::ray_tracer::{hit_depth_and_normal, intersect_surfaces, reflect, refract};
+use crate::vectors::{Vector3d, Vector4d};
+use rand::Rng;
+use std::f64::consts::PI;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/*
+    Monte Carlo path tracer with progressive accumulation.
+
+    Every call of render adds samples_per_pass jittered samples per supersized pixel to the
+    canvas' float accumulation buffer and writes the running average into the color buffer,
+    so calling it every frame shows the image refining over time. Accumulation stops once
+    max_samples or the time budget is reached, Canvas::reset_accumulation starts over.
+
+    Per bounce one lobe of the hit's Material is picked at random with its share:
+    Lambertian (cosine-weighted hemisphere sampling), glossy mirror (reflection blurred by
+    roughness) or refraction. Emissive meshes and the background act as light sources,
+    PointLights are sampled directly at every diffuse bounce (with shadow rays). The scene's
+    SDF shapes are sphere traced next to the meshes and shade as diffuse surfaces.
+
+    The result is not physically based: point lights contribute like in the rasterizer,
+    without the 1/pi of the Lambertian BRDF and without 1/r^2 falloff, so a scene looks
+    the same in all renderers, but direct light is too strong compared to the indirect
+    light gathered by the bounces.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PathTracer {
+    pub max_depth: usize,
+    pub samples_per_pass: usize, // samples per pixel added by every render call
+    pub max_samples: Option<usize>,
+    pub time_budget: Option<Duration>,
+    pub bias: f64, // offset of secondary ray origins along the normal, against self intersection
+}
+
+// bounces after which paths are terminated at random, weighted by their throughput
+const RUSSIAN_ROULETTE_DEPTH: usize = 3;
+
+impl Default for PathTracer {
+    fn default() -> Self {
+        Self::new(8, 1)
+    }
+}
+
+impl PathTracer {
+    pub fn new(max_depth: usize, samples_per_pass: usize) -> Self {
+        Self {
+            max_depth,
+            samples_per_pass,
+            max_samples: None,
+            time_budget: None,
+            bias: 1e-4,
+        }
+    }
+
+    pub fn is_converged(&self, canvas: &Canvas) -> bool {
+        // true once the canvas has accumulated max_samples or spent the time budget
+        self.max_samples
+            .is_some_and(|max_samples| canvas.accumulated_samples >= max_samples)
+            || self
+                .time_budget
+                .is_some_and(|time_budget| canvas.accumulation_time >= time_budget)
+    }
+
+    pub fn render(&self, canvas: &mut Canvas) {
+        /*
+            adds one pass of samples (unless converged) and resolves the running average.
+            every supersized pixel is rewritten, so the usual buffer resets do no harm
+        */
+        let timer = Instant::now();
+        canvas.scene.bvh();
+
+        let (size_x, size_y) = (canvas.size_x_supersized, canvas.size_y_supersized);
+        if canvas.accumulation_buffer_supersized.len() != size_x * size_y {
+            canvas.reset_accumulation();
+            canvas.accumulation_buffer_supersized = vec![Vector3d::zero(); size_x * size_y];
+        }
+
+        if !self.is_converged(canvas) {
+            let mut rng = rand::rng();
+            let mut accumulation = std::mem::take(&mut canvas.accumulation_buffer_supersized);
+            for y in 0..size_y {
+                for x in 0..size_x {
+                    for _ in 0..self.samples_per_pass {
+                        let ray = canvas.camera.calc_pixel_ray(
+                            x as f64 + rng.random::<f64>() - 0.5,
+                            y as f64 + rng.random::<f64>() - 0.5,
+                            size_x,
+                            size_y,
+                        );
+                        accumulation[y * size_x + x] += self.trace(canvas, ray, &mut rng);
+                    }
+                }
+            }
+            canvas.accumulation_buffer_supersized = accumulation;
+            canvas.accumulated_samples += self.samples_per_pass;
+            canvas.accumulation_time += timer.elapsed();
+        }
+        self.resolve(canvas);
+
+        canvas.stats = RenderStats::default();
+        canvas.apply_ssaa();
+        canvas.apply_outline();
+        canvas.apply_selection_outline();
+        canvas.apply_post_effects();
+    }
+
+    fn resolve(&self, canvas: &mut Canvas) {
+        /*
+            writes the average into the color buffer and depth, normal, id and G-buffer
+            entry of the surface at each pixel center into the other buffers
+        */
+        let camera_matrix = canvas.camera.calc_camera_matrix();
+        let projection_matrix = canvas.camera.calc_perspective_projection_matrix();
+        let (size_x, size_y) = (canvas.size_x_supersized, canvas.size_y_supersized);
+        let samples = canvas.accumulated_samples.max(1) as f64;
+
+        for y in 0..size_y {
+            for x in 0..size_x {
+                // pixel rays have y pointing down, just like the rows of the buffers
+                let idx = y * size_x + x;
+                let color = canvas.accumulation_buffer_supersized[idx] / samples;
+
+                let ray = canvas
+                    .camera
+                    .calc_pixel_ray(x as f64, y as f64, size_x, size_y);
+                let hit = intersect_surfaces(canvas, &ray);
+                // the background keeps its alpha, so renders can be composited
+                let alpha = if hit.is_some() {
+                    1.0
+                } else {
+                    canvas.bg_color.u
+                };
+                canvas.buffer_supersized[idx] =
+                    premultiply(&Vector4d::from_vector3d(&color, alpha));
+
+                let surface = hit.map(|hit| {
+                    let (z, normal) = hit_depth_and_normal(
+                        &camera_matrix,
+                        &projection_matrix,
+                        hit.point,
+                        hit.normal,
+                    );
+                    let position = camera_matrix
+                        .times_vec(Vector4d::from_vector3d(&hit.point, 1.0))
+                        .truncate_to_3d();
+                    (z, Surface::new(position, normal, hit.albedo), hit.mesh)
+                });
+                canvas.z_buffer_supersized[idx] = surface.map_or(f64::MAX, |(z, _, _)| z);
+                canvas.normal_buffer_supersized[idx] =
+                    surface.map_or(Vector3d::zero(), |(_, surface, _)| surface.normal);
+                if let Some(id_buffer) = canvas.id_buffer_supersized.as_mut() {
+                    id_buffer[idx] = hit
+                        .and_then(|hit| hit.mesh)
+                        .map(|(mesh, face)| ObjectId::new(mesh, face));
+                }
+                if let Some(g_buffer) = canvas.g_buffer_supersized.as_mut() {
+                    match surface {
+                        Some((_, surface, mesh)) => {
+                            g_buffer.set(idx, &surface, mesh.map(|(mesh, _)| mesh))
+                        }
+                        None => g_buffer.covered[idx] = false,
+                    }
+                }
+            }
+        }
+    }
+
+    fn trace(&self, canvas: &Canvas, mut ray: Ray, rng: &mut impl Rng) -> Vector3d {
+        // radiance along one random path starting with ray
+        let mut radiance = Vector3d::zero();
+        let mut throughput = Vector3d::new(1.0, 1.0, 1.0);
+
+        for depth in 0..self.max_depth {
+            let Some(hit) = intersect_surfaces(canvas, &ray) else {
+                radiance += throughput * canvas.linear_color(&canvas.bg_color).truncate_to_3d();
+                break;
+            };
+
+            let material = hit.material;
+            let albedo = hit.albedo.truncate_to_3d();
+            let normal = hit.normal;
+
+            radiance += throughput * material.emission;
+
+            // pick a lobe, its probability equals its share, so the weights cancel out
+            let lobe = rng.random::<f64>();
+            if lobe < material.transparency {
+                let eta = if hit.front_face {
+                    1.0 / material.ior
+                } else {
+                    material.ior
+                };
+                ray = match refract(ray.direction, normal, eta) {
+                    Some(direction) => Ray::new(hit.point - normal * self.bias, direction),
+                    None => Ray::new(
+                        hit.point + normal * self.bias,
+                        reflect(ray.direction, normal),
+                    ),
+                };
+                throughput *= albedo;
+            } else if lobe < material.transparency + material.reflectivity {
+                let mirrored = reflect(ray.direction, normal);
+                let direction = (mirrored * (1.0 - material.roughness)
+                    + sample_cosine_hemisphere(normal, rng) * material.roughness)
+                    .normalize();
+                if direction.dot(normal) <= 0.0 {
+                    break; // blurred below the surface
+                }
+                ray = Ray::new(hit.point + normal * self.bias, direction);
+                throughput *= albedo;
+            } else {
+                let origin = hit.point + normal * self.bias;
+                radiance += throughput * albedo * self.direct_light(canvas, origin, normal);
+                ray = Ray::new(origin, sample_cosine_hemisphere(normal, rng));
+                throughput *= albedo;
+            }
+
+            if depth >= RUSSIAN_ROULETTE_DEPTH {
+                let survival = throughput.x.max(throughput.y).max(throughput.z).min(1.0);
+                if rng.random::<f64>() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+        radiance
+    }
+
+    fn direct_light(&self, canvas: &Canvas, origin: Vector3d, normal: Vector3d) -> Vector3d {
+        /*
+            light arriving from all unblocked point lights, scaled like the rasterizer's
+            lighting (no BRDF normalization, no distance falloff), not physically based
+        */
+        let mut light_total = Vector3d::zero();
+        for light in &canvas.lights {
+            let to_light = light.pos - origin;
+            let distance = to_light.length();
+            let n_dot_l = normal.dot(to_light / distance);
+            if n_dot_l <= 0.0 {
+                continue;
+            }
+            let blocked = intersect_surfaces(canvas, &Ray::new(origin, to_light))
+                .is_some_and(|hit| hit.t < distance);
+            if !blocked {
+                light_total += canvas.linear_color(&light.emission).truncate_to_3d()
+                    * (light.strength * n_dot_l);
+            }
+        }
+        light_total
+    }
+}
+
+impl fmt::Display for PathTracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Path tracer ({} bounces, {} samples per pass)",
+            self.max_depth, self.samples_per_pass
+        )
+    }
+}
+
+pub fn sample_cosine_hemisphere(normal: Vector3d, rng: &mut impl Rng) -> Vector3d {
+    /*
+        random direction around normal with a density proportional to cos(theta),
+        which cancels out the cosine term of the Lambertian BRDF
+    */
+    let r = rng.random::<f64>().sqrt();
+    let phi = 2.0 * PI * rng.random::<f64>();
+    let (x, y) = (r * phi.cos(), r * phi.sin());
+    let z = (1.0 - r * r).max(0.0).sqrt();
+
+    // orthonormal basis around the normal
+    let helper = if normal.x.abs() > 0.9 {
+        Vector3d::new(0.0, 1.0, 0.0)
+    } else {
+        Vector3d::new(1.0, 0.0, 0.0)
+    };
+    let tangent = normal.cross(helper).normalize();
+    let bitangent = normal.cross(tangent);
+    (tangent * x + bitangent * y + normal * z).normalize()
+}
@@ -0,0 +1,552 @@
+use crate::error::RenderError;
+use crate::util::clamp;
+use crate::vectors::{Vector3d, Vector4d};
+use rand::Rng;
+use std::fs;
+use std::sync::Arc;
+
+/*
+    Post-processing on the resolved image.
+
+    Effects run in order after apply_ssaa, on the output sized image with display encoded
+    [0.0, ... 1.0] rgba (straight alpha, only bloom raises it where its glow lands on
+    transparent pixels). Each one gets the whole image, so effects may read neighbouring
+    pixels. A chain can be built in code or parsed from a text description like
+    "bloom, vignette:0.4, lut:grade.cube" (see parse_post_effects). Effects are Send + Sync,
+    so a canvas can still be moved to or shared with other threads.
+*/
+pub trait PostEffect: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn apply(&self, pixels: &mut [Vector4d], size_x: usize, size_y: usize);
+}
+
+// effects are shared, so canvases holding a chain stay cheap to clone
+pub type PostEffectChain = Vec<Arc<dyn PostEffect>>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bloom {
+    pub threshold: f64, // brightness above which pixels glow
+    pub radius: f64,    // standard deviation of the blur, in pixels
+    pub strength: f64,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            threshold: 0.8,
+            radius: 4.0,
+            strength: 1.0,
+        }
+    }
+}
+
+impl PostEffect for Bloom {
+    fn name(&self) -> &str {
+        "bloom"
+    }
+
+    fn apply(&self, pixels: &mut [Vector4d], size_x: usize, size_y: usize) {
+        // bright pass weighted by coverage, blurred and added back on top
+        let bright: Vec<Vector4d> = pixels
+            .iter()
+            .map(|pixel| {
+                Vector4d::new(
+                    (pixel.x - self.threshold).max(0.0) * pixel.u,
+                    (pixel.y - self.threshold).max(0.0) * pixel.u,
+                    (pixel.z - self.threshold).max(0.0) * pixel.u,
+                    0.0,
+                )
+            })
+            .collect();
+        let glow = gaussian_blur(&bright, size_x, size_y, self.radius);
+        for (pixel, glow) in pixels.iter_mut().zip(glow) {
+            /*
+                the glow is light added over the pixel, so it also covers transparent
+                pixels as much as its brightest channel. opaque pixels simply get it added
+            */
+            let glow = glow * self.strength;
+            let glow_alpha = clamp(glow.x.max(glow.y).max(glow.z));
+            let alpha = pixel.u + glow_alpha * (1.0 - pixel.u);
+            if alpha <= 0.0 {
+                continue;
+            }
+            *pixel = clamp_rgb(Vector4d::new(
+                (pixel.x * pixel.u + glow.x) / alpha,
+                (pixel.y * pixel.u + glow.y) / alpha,
+                (pixel.z * pixel.u + glow.z) / alpha,
+                alpha,
+            ));
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Vignette {
+    pub strength: f64, // darkening in the corners, [0.0, ... 1.0]
+    pub radius: f64,   // distance from the center (1.0 at the corners) where darkening starts
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self {
+            strength: 0.5,
+            radius: 0.5,
+        }
+    }
+}
+
+impl PostEffect for Vignette {
+    fn name(&self) -> &str {
+        "vignette"
+    }
+
+    fn apply(&self, pixels: &mut [Vector4d], size_x: usize, size_y: usize) {
+        for y in 0..size_y {
+            for x in 0..size_x {
+                let dist = normalized_center_offset(x, y, size_x, size_y);
+                let dist = (dist.0 * dist.0 + dist.1 * dist.1).sqrt() / 2.0_f64.sqrt();
+                let t = clamp((dist - self.radius) / (1.0 - self.radius).max(f64::EPSILON));
+                let factor = 1.0 - self.strength * t * t * (3.0 - 2.0 * t);
+
+                let pixel = &mut pixels[y * size_x + x];
+                pixel.x *= factor;
+                pixel.y *= factor;
+                pixel.z *= factor;
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChromaticAberration {
+    pub strength: f64, // offset of red and blue in the corners, in pixels
+}
+
+impl Default for ChromaticAberration {
+    fn default() -> Self {
+        Self { strength: 3.0 }
+    }
+}
+
+impl PostEffect for ChromaticAberration {
+    fn name(&self) -> &str {
+        "chromatic aberration"
+    }
+
+    fn apply(&self, pixels: &mut [Vector4d], size_x: usize, size_y: usize) {
+        // red is pushed outwards and blue inwards, growing towards the borders
+        let source = pixels.to_vec();
+        let sample = |x: f64, y: f64| -> Vector4d {
+            let x = (x.round().max(0.0) as usize).min(size_x - 1);
+            let y = (y.round().max(0.0) as usize).min(size_y - 1);
+            source[y * size_x + x]
+        };
+        for y in 0..size_y {
+            for x in 0..size_x {
+                let (dx, dy) = normalized_center_offset(x, y, size_x, size_y);
+                let (shift_x, shift_y) = (dx * self.strength, dy * self.strength);
+                let pixel = &mut pixels[y * size_x + x];
+                pixel.x = sample(x as f64 - shift_x, y as f64 - shift_y).x;
+                pixel.z = sample(x as f64 + shift_x, y as f64 + shift_y).z;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut3d {
+    pub size: usize,
+    pub table: Vec<Vector3d>, // size^3 output colors, red changing fastest
+}
+
+impl Lut3d {
+    pub fn identity(size: usize) -> Self {
+        Self::from_fn(size, |color| color)
+    }
+
+    pub fn from_fn(size: usize, grade: impl Fn(Vector3d) -> Vector3d) -> Self {
+        let size = size.max(2);
+        let step = 1.0 / (size - 1) as f64;
+        let mut table = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    table.push(grade(Vector3d::new(
+                        r as f64 * step,
+                        g as f64 * step,
+                        b as f64 * step,
+                    )));
+                }
+            }
+        }
+        Self { size, table }
+    }
+
+    pub fn load_cube(path: &str) -> Result<Self, RenderError> {
+        /*
+            reads the .cube format most grading tools export: a LUT_3D_SIZE line
+            and size^3 lines of "r g b", red changing fastest. other keywords are skipped
+        */
+        let contents = fs::read_to_string(path)?;
+        let parse_error = |line: usize, reason: String| RenderError::Parse {
+            source: path.to_string(),
+            line,
+            reason,
+        };
+
+        let mut size = None;
+        let mut table = vec![];
+        for (line_no, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(value) = line.strip_prefix("LUT_3D_SIZE") {
+                size = Some(value.trim().parse::<usize>().map_err(|_| {
+                    parse_error(
+                        line_no + 1,
+                        format!("invalid LUT size \"{}\"", value.trim()),
+                    )
+                })?);
+            } else if line.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') {
+                let values = line
+                    .split_whitespace()
+                    .map(|value| value.parse::<f64>())
+                    .collect::<Result<Vec<f64>, _>>();
+                match values {
+                    Ok(values) if values.len() == 3 => {
+                        table.push(Vector3d::new(values[0], values[1], values[2]))
+                    }
+                    _ => {
+                        return Err(parse_error(
+                            line_no + 1,
+                            format!("expected 3 values, found \"{}\"", line),
+                        ));
+                    }
+                }
+            }
+        }
+
+        let size = size.ok_or_else(|| parse_error(1, "missing LUT_3D_SIZE".to_string()))?;
+        if size < 2 || table.len() != size * size * size {
+            return Err(parse_error(
+                contents.lines().count(),
+                format!(
+                    "expected {} entries for size {}, found {}",
+                    size * size * size,
+                    size,
+                    table.len()
+                ),
+            ));
+        }
+        Ok(Self { size, table })
+    }
+
+    pub fn lookup(&self, color: Vector3d) -> Vector3d {
+        // trilinear interpolation between the 8 surrounding entries
+        let max = (self.size - 1) as f64;
+        let scaled = [
+            clamp(color.x) * max,
+            clamp(color.y) * max,
+            clamp(color.z) * max,
+        ];
+        let lower = scaled.map(|c| (c.floor() as usize).min(self.size - 2));
+        let t = [
+            scaled[0] - lower[0] as f64,
+            scaled[1] - lower[1] as f64,
+            scaled[2] - lower[2] as f64,
+        ];
+        let entry = |r: usize, g: usize, b: usize| {
+            self.table
+                [(lower[2] + b) * self.size * self.size + (lower[1] + g) * self.size + lower[0] + r]
+        };
+
+        let mut result = Vector3d::zero();
+        for corner in 0..8 {
+            let (r, g, b) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let weight = (if r == 1 { t[0] } else { 1.0 - t[0] })
+                * (if g == 1 { t[1] } else { 1.0 - t[1] })
+                * (if b == 1 { t[2] } else { 1.0 - t[2] });
+            result += entry(r, g, b) * weight;
+        }
+        result
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorGrading {
+    pub lut: Lut3d,
+    pub strength: f64, // mix between the original and the graded color
+}
+
+impl ColorGrading {
+    pub fn new(lut: Lut3d) -> Self {
+        Self { lut, strength: 1.0 }
+    }
+}
+
+impl PostEffect for ColorGrading {
+    fn name(&self) -> &str {
+        "color grading"
+    }
+
+    fn apply(&self, pixels: &mut [Vector4d], _size_x: usize, _size_y: usize) {
+        for pixel in pixels.iter_mut() {
+            let original = pixel.truncate_to_3d();
+            let graded = self.lut.lookup(original);
+            let mixed = original * (1.0 - self.strength) + graded * self.strength;
+            *pixel = clamp_rgb(Vector4d::from_vector3d(&mixed, pixel.u));
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FilmGrain {
+    pub strength: f64, // amplitude of the noise
+}
+
+impl Default for FilmGrain {
+    fn default() -> Self {
+        Self { strength: 0.05 }
+    }
+}
+
+impl PostEffect for FilmGrain {
+    fn name(&self) -> &str {
+        "film grain"
+    }
+
+    fn apply(&self, pixels: &mut [Vector4d], _size_x: usize, _size_y: usize) {
+        // monochrome noise, new every frame
+        let mut rng = rand::rng();
+        for pixel in pixels.iter_mut() {
+            let noise = (rng.random::<f64>() - 0.5) * self.strength;
+            *pixel = clamp_rgb(*pixel + Vector4d::new(noise, noise, noise, 0.0));
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sharpen {
+    pub amount: f64,
+    pub radius: f64, // of the unsharp mask's blur, in pixels
+}
+
+impl Default for Sharpen {
+    fn default() -> Self {
+        Self {
+            amount: 0.5,
+            radius: 1.0,
+        }
+    }
+}
+
+impl PostEffect for Sharpen {
+    fn name(&self) -> &str {
+        "sharpen"
+    }
+
+    fn apply(&self, pixels: &mut [Vector4d], size_x: usize, size_y: usize) {
+        // unsharp mask, amplifies the difference to a blurred copy
+        let blurred = gaussian_blur(pixels, size_x, size_y, self.radius);
+        for (pixel, blurred) in pixels.iter_mut().zip(blurred) {
+            let mut detail = *pixel - blurred;
+            detail.u = 0.0;
+            *pixel = clamp_rgb(*pixel + detail * self.amount);
+        }
+    }
+}
+
+pub fn gaussian_blur(
+    pixels: &[Vector4d],
+    size_x: usize,
+    size_y: usize,
+    sigma: f64,
+) -> Vec<Vector4d> {
+    /*
+        separable gaussian blur, a horizontal and a vertical pass with a kernel
+        of 3 sigma in each direction. borders repeat the outermost pixels
+    */
+    if sigma <= 0.0 {
+        return pixels.to_vec();
+    }
+    let half_width = (sigma * 3.0).ceil() as i64;
+    let mut kernel: Vec<f64> = (-half_width..=half_width)
+        .map(|i| (-((i * i) as f64) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    kernel.iter_mut().for_each(|weight| *weight /= sum);
+
+    let pass = |source: &[Vector4d], horizontal: bool| -> Vec<Vector4d> {
+        let mut target = vec![Vector4d::zeros(); source.len()];
+        for y in 0..size_y {
+            for x in 0..size_x {
+                let mut sum = Vector4d::zeros();
+                for (k, weight) in kernel.iter().enumerate() {
+                    let offset = k as i64 - half_width;
+                    let (x_, y_) = if horizontal {
+                        ((x as i64 + offset).clamp(0, size_x as i64 - 1) as usize, y)
+                    } else {
+                        (x, (y as i64 + offset).clamp(0, size_y as i64 - 1) as usize)
+                    };
+                    sum += source[y_ * size_x + x_] * *weight;
+                }
+                target[y * size_x + x] = sum;
+            }
+        }
+        target
+    };
+    pass(&pass(pixels, true), false)
+}
+
+pub fn parse_post_effects(spec: &str) -> Result<PostEffectChain, RenderError> {
+    /*
+        builds a chain from comma separated effect names, each optionally followed by
+        ":parameter", e.g. "bloom:0.7, vignette:0.4, chromatic:2, lut:grade.cube, grain, sharpen".
+        accepted names, aliases in brackets:
+            bloom                 threshold
+            vignette              strength
+            chromatic_aberration  offset in pixels   (chromatic, aberration, "chromatic aberration")
+            lut                   .cube file         (color_grading, "color grading")
+            grain                 strength           (film_grain, "film grain")
+            sharpen               amount
+    */
+    let mut chain: PostEffectChain = vec![];
+    for entry in spec
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let (name, parameter) = match entry.split_once(':') {
+            Some((name, parameter)) => (name.trim(), Some(parameter.trim())),
+            None => (entry, None),
+        };
+        let number = |default: f64| -> Result<f64, RenderError> {
+            parameter.map_or(Ok(default), |parameter| {
+                parameter.parse::<f64>().map_err(|_| {
+                    RenderError::InvalidArgument(format!(
+                        "invalid parameter \"{}\" for post effect \"{}\"",
+                        parameter, name
+                    ))
+                })
+            })
+        };
+
+        let effect: Arc<dyn PostEffect> = match name {
+            "bloom" => Arc::new(Bloom {
+                threshold: number(Bloom::default().threshold)?,
+                ..Bloom::default()
+            }),
+            "vignette" => Arc::new(Vignette {
+                strength: number(Vignette::default().strength)?,
+                ..Vignette::default()
+            }),
+            "chromatic_aberration" | "chromatic" | "aberration" | "chromatic aberration" => {
+                Arc::new(ChromaticAberration {
+                    strength: number(ChromaticAberration::default().strength)?,
+                })
+            }
+            "lut" | "color_grading" | "color grading" => {
+                let path = parameter.ok_or_else(|| {
+                    RenderError::InvalidArgument(format!("post effect \"{}\" needs a file", name))
+                })?;
+                Arc::new(ColorGrading::new(Lut3d::load_cube(path)?))
+            }
+            "grain" | "film_grain" | "film grain" => Arc::new(FilmGrain {
+                strength: number(FilmGrain::default().strength)?,
+            }),
+            "sharpen" => Arc::new(Sharpen {
+                amount: number(Sharpen::default().amount)?,
+                ..Sharpen::default()
+            }),
+            _ => {
+                return Err(RenderError::InvalidArgument(format!(
+                    "unknown post effect \"{}\"",
+                    name
+                )));
+            }
+        };
+        chain.push(effect);
+    }
+    Ok(chain)
+}
+
+fn normalized_center_offset(x: usize, y: usize, size_x: usize, size_y: usize) -> (f64, f64) {
+    // offset of a pixel from the image center, -1.0 to 1.0 on both axes
+    (
+        (x as f64 + 0.5) / size_x as f64 * 2.0 - 1.0,
+        (y as f64 + 0.5) / size_y as f64 * 2.0 - 1.0,
+    )
+}
+
+fn clamp_rgb(color: Vector4d) -> Vector4d {
+    Vector4d::new(clamp(color.x), clamp(color.y), clamp(color.z), color.u)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn write_cube(name: &str, contents: &str) -> String {
+        let path = env::temp_dir().join(format!("rusty_ruling_pen_{}.cube", name));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn parses_names_aliases_and_parameters() {
+        let chain =
+            parse_post_effects(" bloom:0.7, chromatic, film grain:0.1 ,, sharpen ").unwrap();
+        let names: Vec<&str> = chain.iter().map(|effect| effect.name()).collect();
+        assert_eq!(
+            names,
+            ["bloom", "chromatic aberration", "film grain", "sharpen"]
+        );
+        assert!(parse_post_effects("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_effects_and_bad_parameters() {
+        for spec in ["bloom, blur", "vignette:strong", "lut"] {
+            assert!(matches!(
+                parse_post_effects(spec),
+                Err(RenderError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn loads_cube_files() {
+        let identity = Lut3d::identity(2);
+        let mut contents = "# identity\nTITLE \"test\"\nLUT_3D_SIZE 2\n\n".to_string();
+        for entry in &identity.table {
+            contents += &format!("{} {} {}\n", entry.x, entry.y, entry.z);
+        }
+        let path = write_cube("identity", &contents);
+        assert_eq!(Lut3d::load_cube(&path).unwrap(), identity);
+
+        let chain = parse_post_effects(&format!("lut:{}", path)).unwrap();
+        assert_eq!(chain[0].name(), "color grading");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_malformed_cube_files() {
+        for (name, contents, line) in [
+            ("no_size", "0 0 0\n1 1 1\n", 1),
+            ("bad_size", "LUT_3D_SIZE two\n", 1),
+            ("short_entry", "LUT_3D_SIZE 2\n0 0\n", 2),
+            ("missing_entries", "LUT_3D_SIZE 2\n0 0 0\n1 1 1\n", 3),
+        ] {
+            let path = write_cube(name, contents);
+            let result = Lut3d::load_cube(&path);
+            fs::remove_file(&path).unwrap();
+            match result {
+                Err(RenderError::Parse { line: found, .. }) => assert_eq!(found, line, "{}", name),
+                other => panic!("{}: expected a parse error, got {:?}", name, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::graphics::aabb::Aabb;
+use crate::vectors::Vector3d;
+use std::fmt;
+
+const EPSILON: f64 = 1e-9;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vector3d,
+    pub direction: Vector3d, // normalized
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RayHit {
+    pub mesh: usize,
+    pub face: usize,
+    pub t: f64, // distance along the ray
+
+    pub point: Vector3d,  // world space
+    pub normal: Vector3d, // world space, facing against the ray
+    pub front_face: bool, // hit the side the face winding points to, i.e. entering the mesh
+    pub u: f64,           // barycentric coordinates of the hit in its face
+    pub v: f64,
+}
+
+impl Ray {
+    pub fn new(origin: Vector3d, direction: Vector3d) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    pub fn at(&self, t: f64) -> Vector3d {
+        self.origin + self.direction * t
+    }
+
+    pub fn intersect_triangle(
+        &self,
+        p1: &Vector3d,
+        p2: &Vector3d,
+        p3: &Vector3d,
+    ) -> Option<(f64, f64, f64)> {
+        /*
+            Möller–Trumbore, both sides of the triangle count.
+            returns t and the barycentric coordinates u, v of the hit (weights of p2 and p3)
+        */
+        let edge1 = *p2 - *p1;
+        let edge2 = *p3 - *p1;
+        let h = self.direction.cross(edge2);
+        let a = edge1.dot(h);
+        if a.abs() < EPSILON {
+            return None; // parallel to the triangle
+        }
+
+        let f = 1.0 / a;
+        let s = self.origin - *p1;
+        let u = f * s.dot(h);
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(edge1);
+        let v = f * self.direction.dot(q);
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = f * edge2.dot(q);
+        if t <= EPSILON {
+            return None; // behind the origin
+        }
+        Some((t, u, v))
+    }
+
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<(f64, f64)> {
+        /*
+            slab test, returns entry and exit distance
+        */
+        if aabb.is_empty() {
+            return None;
+        }
+        let mut t_min = 0.0_f64;
+        let mut t_max = f64::MAX;
+        for (origin, direction, min, max) in [
+            (self.origin.x, self.direction.x, aabb.min.x, aabb.max.x),
+            (self.origin.y, self.direction.y, aabb.min.y, aabb.max.y),
+            (self.origin.z, self.direction.z, aabb.min.z, aabb.max.z),
+        ] {
+            if direction.abs() < EPSILON {
+                if origin < min || origin > max {
+                    return None;
+                }
+                continue;
+            }
+            let t_1 = (min - origin) / direction;
+            let t_2 = (max - origin) / direction;
+            t_min = t_min.max(t_1.min(t_2));
+            t_max = t_max.min(t_1.max(t_2));
+            if t_min > t_max {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+}
+
+impl fmt::Display for Ray {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Ray from {} towards {}", self.origin, self.direction)
+    }
+}
+
+impl fmt::Display for RayHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "mesh {}, face {} at {} with normal {}",
+            self.mesh, self.face, self.point, self.normal
+        )
+    }
+}
@@ -0,0 +1,281 @@
+use crate::graphics::PointLight;
+use crate::graphics::canvas::{Canvas, RenderStats};
+use crate::graphics::fragment_shader::shade_frag;
+use crate::graphics::gbuffer::Surface;
+use crate::graphics::material::Material;
+use crate::graphics::object_id::ObjectId;
+use crate::graphics::ray::Ray;
+use crate::graphics::sdf::sphere_trace;
+use crate::vectors::matrices::Matrix4x4;
+use crate::vectors::{Vector3d, Vector4d};
+use std::fmt;
+
+/*
+    Whitted style ray tracer, an alternative to the rasterizer working on the same
+    Scene, Camera and PointLights and writing into the same buffers of a Canvas.
+
+    One primary ray per supersized pixel, so SSAA, outlines and the ID buffer work the same
+    as for rasterized frames. At every hit the surface is shaded with the canvas' shading
+    mode, using only lights not blocked by other geometry (hard shadows), then mixed with
+    mirrored and refracted rays according to the mesh's Material. The scene's SDF shapes
+    are sphere traced next to the meshes and shade as diffuse surfaces.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RayTracer {
+    pub max_depth: usize, // number of reflection/refraction bounces
+    pub bias: f64, // offset of secondary ray origins along the normal, against self intersection
+}
+
+// closest surface along a ray, a mesh face or an SDF shape
+#[derive(Copy, Clone, Debug)]
+pub struct SurfaceHit {
+    pub t: f64,
+    pub point: Vector3d,
+    pub normal: Vector3d, // world space, facing against the ray
+    pub front_face: bool,
+    pub mesh: Option<(usize, usize)>, // mesh and face index, None for SDF shapes
+    pub material: Material,
+    pub albedo: Vector4d, // linear
+}
+
+pub fn intersect_surfaces(canvas: &Canvas, ray: &Ray) -> Option<SurfaceHit> {
+    /*
+        nearest of the mesh hit (through the BVH as it is) and the sphere traced SDF hit,
+        shared by the ray and path tracer
+    */
+    let mesh_hit = canvas.scene.intersect(ray).map(|hit| SurfaceHit {
+        t: hit.t,
+        point: hit.point,
+        normal: hit.normal,
+        front_face: hit.front_face,
+        mesh: Some((hit.mesh, hit.face)),
+        material: canvas.scene.meshes()[hit.mesh].material,
+        albedo: canvas.linear_mesh_color(hit.mesh),
+    });
+    let sdf_hit = sphere_trace(&canvas.scene.sdf_shapes, ray).map(|hit| {
+        let front_face = hit.normal.dot(ray.direction) <= 0.0;
+        SurfaceHit {
+            t: hit.t,
+            point: hit.point,
+            normal: if front_face {
+                hit.normal
+            } else {
+                hit.normal * -1.0
+            },
+            front_face,
+            mesh: None,
+            material: Material::default(),
+            albedo: canvas.linear_color(&canvas.scene.sdf_shapes[hit.shape].color),
+        }
+    });
+    match (mesh_hit, sdf_hit) {
+        (Some(mesh_hit), Some(sdf_hit)) if sdf_hit.t < mesh_hit.t => Some(sdf_hit),
+        (Some(mesh_hit), _) => Some(mesh_hit),
+        (None, sdf_hit) => sdf_hit,
+    }
+}
+
+impl Default for RayTracer {
+    fn default() -> Self {
+        Self::new(5)
+    }
+}
+
+impl RayTracer {
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            max_depth,
+            bias: 1e-4,
+        }
+    }
+
+    pub fn render(&self, canvas: &mut Canvas) {
+        /*
+            traces the scene into the supersized buffers, then resolves it like render_scene_to_buffer.
+            expects reset() and reset_z_buffer() to be called before, as for the rasterizer
+        */
+        canvas.scene.bvh();
+
+        let camera_matrix = canvas.camera.calc_camera_matrix();
+        let projection_matrix = canvas.camera.calc_perspective_projection_matrix();
+        let (size_x, size_y) = (canvas.size_x_supersized, canvas.size_y_supersized);
+
+        // trace everything first, the scene stays borrowed while tracing
+        let mut fragments = vec![];
+        for y in 0..size_y {
+            for x in 0..size_x {
+                let ray = canvas
+                    .camera
+                    .calc_pixel_ray(x as f64, y as f64, size_x, size_y);
+                let Some(hit) = intersect_surfaces(canvas, &ray) else {
+                    continue;
+                };
+                let color = self.shade(canvas, &ray, &hit, 0);
+
+                let (z_projected, normal_cam_space) =
+                    hit_depth_and_normal(&camera_matrix, &projection_matrix, hit.point, hit.normal);
+                let position_cam_space = camera_matrix
+                    .times_vec(Vector4d::from_vector3d(&hit.point, 1.0))
+                    .truncate_to_3d();
+
+                // buffer rows are flipped, pixel rays have y pointing down
+                let coords = (x as i32, (size_y - 1 - y) as i32);
+                fragments.push((
+                    coords,
+                    z_projected,
+                    Surface::new(position_cam_space, normal_cam_space, hit.albedo),
+                    color,
+                    hit.mesh.map(|(mesh, face)| ObjectId::new(mesh, face)),
+                ));
+            }
+        }
+
+        for (coords, z, surface, color, id) in fragments {
+            canvas.set_pixel_with_z(coords, z, &surface, &color, id);
+        }
+        canvas.resolve_transparency();
+        canvas.stats = RenderStats::default();
+
+        canvas.apply_ssaa();
+        canvas.apply_outline();
+        canvas.apply_selection_outline();
+        canvas.apply_post_effects();
+    }
+
+    pub fn trace(&self, canvas: &Canvas, ray: &Ray, depth: usize) -> Vector4d {
+        // color seen along the ray, the background color if nothing is hit
+        match intersect_surfaces(canvas, ray) {
+            Some(hit) => self.shade(canvas, ray, &hit, depth),
+            None => canvas.linear_color(&canvas.bg_color),
+        }
+    }
+
+    fn shade(&self, canvas: &Canvas, ray: &Ray, hit: &SurfaceHit, depth: usize) -> Vector4d {
+        let material = hit.material;
+        let color = hit.albedo;
+
+        let normal = if let (Some((mesh_idx, face_idx)), false) = (hit.mesh, canvas.render_smooth) {
+            let mesh = &canvas.scene.meshes()[mesh_idx];
+            let face = &mesh.faces[face_idx];
+            let (p1, p2, p3) = (
+                mesh.world_vertex(face[0]),
+                mesh.world_vertex(face[1]),
+                mesh.world_vertex(face[2]),
+            );
+            let face_normal = (p2 - p1).cross(p3 - p1).normalize();
+            if face_normal.dot(ray.direction) > 0.0 {
+                face_normal * -1.0
+            } else {
+                face_normal
+            }
+        } else {
+            hit.normal
+        };
+
+        let mut lights = self.visible_lights(canvas, hit.point, normal);
+        for light in lights.iter_mut() {
+            light.emission = canvas.linear_color(&light.emission);
+        }
+        let mut local = shade_frag(
+            canvas.shading_mode,
+            hit.point,
+            normal,
+            ray.direction * -1.0,
+            color,
+            &lights,
+        ) + Vector4d::from_vector3d(&material.emission, 0.0);
+        // only the first hit is traced per pixel, see-through surfaces go through
+        // Material::transparency, a color alpha below 1 would blend the background again
+        local.u = 1.0;
+        if depth >= self.max_depth || material.local_share() >= 1.0 {
+            return local;
+        }
+
+        let mut reflected_share = material.reflectivity;
+        let mut mixed = local * material.local_share();
+        if material.transparency > 0.0 {
+            let eta = if hit.front_face {
+                1.0 / material.ior
+            } else {
+                material.ior
+            };
+            match refract(ray.direction, normal, eta) {
+                Some(direction) => {
+                    let refracted = Ray::new(hit.point - normal * self.bias, direction);
+                    mixed += self.trace(canvas, &refracted, depth + 1) * material.transparency;
+                }
+                // total internal reflection, everything is mirrored
+                None => reflected_share += material.transparency,
+            }
+        }
+        if reflected_share > 0.0 {
+            let reflected = Ray::new(
+                hit.point + normal * self.bias,
+                reflect(ray.direction, normal),
+            );
+            mixed += self.trace(canvas, &reflected, depth + 1) * reflected_share;
+        }
+
+        mixed.u = 1.0;
+        mixed
+    }
+
+    fn visible_lights(
+        &self,
+        canvas: &Canvas,
+        point: Vector3d,
+        normal: Vector3d,
+    ) -> Vec<PointLight> {
+        // lights with a free line of sight to the point, meshes and SDF shapes cast shadows
+        let origin = point + normal * self.bias;
+        canvas
+            .lights
+            .iter()
+            .filter(|light| {
+                let to_light = light.pos - origin;
+                let distance = to_light.length();
+                intersect_surfaces(canvas, &Ray::new(origin, to_light))
+                    .is_none_or(|hit| hit.t >= distance)
+            })
+            .copied()
+            .collect()
+    }
+}
+
+impl fmt::Display for RayTracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Ray tracer ({} bounces)", self.max_depth)
+    }
+}
+
+pub fn hit_depth_and_normal(
+    camera_matrix: &Matrix4x4,
+    projection_matrix: &Matrix4x4,
+    point: Vector3d,
+    normal: Vector3d,
+) -> (f64, Vector3d) {
+    // same depth and normal as the rasterizer stores, so outlines work on traced frames too
+    let point_cam_space = camera_matrix.times_vec(Vector4d::from_vector3d(&point, 1.0));
+    let z_projected = projection_matrix.times_vec(point_cam_space).z;
+    let normal_cam_space = camera_matrix
+        .times_vec(Vector4d::from_vector3d(&normal, 0.0))
+        .truncate_to_3d();
+    (z_projected, normal_cam_space)
+}
+
+pub fn reflect(direction: Vector3d, normal: Vector3d) -> Vector3d {
+    direction - normal * (2.0 * direction.dot(normal))
+}
+
+pub fn refract(direction: Vector3d, normal: Vector3d, eta: f64) -> Option<Vector3d> {
+    /*
+        Snell's law for a normal facing against the direction, eta = n_from / n_to.
+        None on total internal reflection
+    */
+    let cos_i = -direction.dot(normal);
+    let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+    if k < 0.0 {
+        return None;
+    }
+    Some(direction * eta + normal * (eta * cos_i - k.sqrt()))
+}
@@ -0,0 +1,359 @@
+use crate::error::RenderError;
+use crate::vectors::Vector4d;
+use std::f64::consts::PI;
+use std::fmt;
+
+/*
+    Resampling of the supersized buffer to the output resolution, axis by axis.
+
+    Every output pixel is a weighted sum of the supersized pixels around its center. When
+    downsampling (antialiasing mode) the reconstruction filter is stretched over the footprint
+    of the output pixel, so its radius is given in output pixels. When upscaling the filter
+    interpolates between the supersized pixels and its radius is given in those. Filters with
+    negative lobes (Mitchell, Lanczos, bicubic) sharpen but may ring at hard edges.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReconstructionFilter {
+    Box,      // plain average of the footprint, the classic SSAA resolve
+    Tent,     // linear falloff, slightly softer
+    Gaussian, // smooth, no ringing, a bit blurry
+    Mitchell, // Mitchell-Netravali cubic with B = C = 1/3, the usual compromise
+    Lanczos,  // windowed sinc with 3 lobes, sharpest
+}
+
+impl fmt::Display for ReconstructionFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReconstructionFilter::Box => write!(f, "Box"),
+            ReconstructionFilter::Tent => write!(f, "Tent"),
+            ReconstructionFilter::Gaussian => write!(f, "Gaussian"),
+            ReconstructionFilter::Mitchell => write!(f, "Mitchell"),
+            ReconstructionFilter::Lanczos => write!(f, "Lanczos"),
+        }
+    }
+}
+
+impl ReconstructionFilter {
+    pub fn from_name(name: &str) -> Result<Self, RenderError> {
+        match name.trim().to_lowercase().as_str() {
+            "box" => Ok(ReconstructionFilter::Box),
+            "tent" => Ok(ReconstructionFilter::Tent),
+            "gaussian" => Ok(ReconstructionFilter::Gaussian),
+            "mitchell" => Ok(ReconstructionFilter::Mitchell),
+            "lanczos" => Ok(ReconstructionFilter::Lanczos),
+            _ => Err(RenderError::InvalidArgument(format!(
+                "unknown reconstruction filter \"{}\"",
+                name
+            ))),
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        // cycles through all filters, handy for toggling in the viewer
+        match self {
+            ReconstructionFilter::Box => ReconstructionFilter::Tent,
+            ReconstructionFilter::Tent => ReconstructionFilter::Gaussian,
+            ReconstructionFilter::Gaussian => ReconstructionFilter::Mitchell,
+            ReconstructionFilter::Mitchell => ReconstructionFilter::Lanczos,
+            ReconstructionFilter::Lanczos => ReconstructionFilter::Box,
+        }
+    }
+
+    pub fn radius(&self) -> f64 {
+        match self {
+            ReconstructionFilter::Box => 0.5,
+            ReconstructionFilter::Tent => 1.0,
+            ReconstructionFilter::Gaussian => 1.5,
+            ReconstructionFilter::Mitchell => 2.0,
+            ReconstructionFilter::Lanczos => 3.0,
+        }
+    }
+
+    pub fn weight(&self, x: f64) -> f64 {
+        match self {
+            ReconstructionFilter::Box => box_weight(x),
+            ReconstructionFilter::Tent => tent_weight(x),
+            ReconstructionFilter::Gaussian => (-2.0 * x * x).exp(), // sigma 0.5
+            ReconstructionFilter::Mitchell => cubic_weight(x, 1.0 / 3.0, 1.0 / 3.0),
+            ReconstructionFilter::Lanczos => lanczos_weight(x, 3.0),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UpscaleFilter {
+    Nearest,  // repeats the supersized pixels, blocky
+    Bilinear, // linear interpolation between the 2x2 nearest pixels
+    Bicubic,  // Catmull-Rom spline through the 4x4 nearest pixels, sharper
+}
+
+impl fmt::Display for UpscaleFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpscaleFilter::Nearest => write!(f, "Nearest"),
+            UpscaleFilter::Bilinear => write!(f, "Bilinear"),
+            UpscaleFilter::Bicubic => write!(f, "Bicubic"),
+        }
+    }
+}
+
+impl UpscaleFilter {
+    pub fn from_name(name: &str) -> Result<Self, RenderError> {
+        match name.trim().to_lowercase().as_str() {
+            "nearest" => Ok(UpscaleFilter::Nearest),
+            "bilinear" => Ok(UpscaleFilter::Bilinear),
+            "bicubic" => Ok(UpscaleFilter::Bicubic),
+            _ => Err(RenderError::InvalidArgument(format!(
+                "unknown upscale filter \"{}\"",
+                name
+            ))),
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            UpscaleFilter::Nearest => UpscaleFilter::Bilinear,
+            UpscaleFilter::Bilinear => UpscaleFilter::Bicubic,
+            UpscaleFilter::Bicubic => UpscaleFilter::Nearest,
+        }
+    }
+
+    pub fn radius(&self) -> f64 {
+        match self {
+            UpscaleFilter::Nearest => 0.5,
+            UpscaleFilter::Bilinear => 1.0,
+            UpscaleFilter::Bicubic => 2.0,
+        }
+    }
+
+    pub fn weight(&self, x: f64) -> f64 {
+        match self {
+            UpscaleFilter::Nearest => box_weight(x),
+            UpscaleFilter::Bilinear => tent_weight(x),
+            UpscaleFilter::Bicubic => cubic_weight(x, 0.0, 0.5),
+        }
+    }
+}
+
+fn box_weight(x: f64) -> f64 {
+    // half open, so neighbouring output pixels never share a supersized pixel
+    if (-0.5..0.5).contains(&x) { 1.0 } else { 0.0 }
+}
+
+fn tent_weight(x: f64) -> f64 {
+    (1.0 - x.abs()).max(0.0)
+}
+
+fn cubic_weight(x: f64, b: f64, c: f64) -> f64 {
+    // Mitchell-Netravali family of cubics, B = 0 and C = 0.5 is Catmull-Rom
+    let x = x.abs();
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b))
+            / 6.0
+    } else if x < 2.0 {
+        ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        0.0
+    }
+}
+
+fn lanczos_weight(x: f64, lobes: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else if x.abs() < lobes {
+        let pi_x = PI * x;
+        lobes * pi_x.sin() * (pi_x / lobes).sin() / (pi_x * pi_x)
+    } else {
+        0.0
+    }
+}
+
+// first source pixel and the weights of it and its successors, per destination pixel
+type AxisTaps = Vec<(usize, Vec<f64>)>;
+
+fn calc_axis_taps(
+    size_src: usize,
+    size_dst: usize,
+    radius: f64,
+    weight: &dyn Fn(f64) -> f64,
+) -> AxisTaps {
+    // stretch the filter over the footprint when downsampling, keep it when upscaling
+    let ratio = size_src as f64 / size_dst as f64;
+    let scale = ratio.max(1.0);
+    (0..size_dst)
+        .map(|i| {
+            let center = (i as f64 + 0.5) * ratio;
+            let lower = (center - radius * scale).floor().max(0.0) as usize;
+            let upper = ((center + radius * scale).ceil() as usize).min(size_src);
+            let mut weights: Vec<f64> = (lower..upper)
+                .map(|j| weight((j as f64 + 0.5 - center) / scale))
+                .collect();
+            let sum: f64 = weights.iter().sum();
+            if sum.abs() < f64::EPSILON {
+                // nothing in reach, fall back to the nearest source pixel
+                let nearest = (center as usize).min(size_src - 1);
+                return (nearest, vec![1.0]);
+            }
+            // normalize, taps cut off at the border are dropped
+            weights.iter_mut().for_each(|w| *w /= sum);
+            (lower, weights)
+        })
+        .collect()
+}
+
+pub fn resample(
+    pixels: &[Vector4d],
+    size_src: (usize, usize),
+    size_dst: (usize, usize),
+    downsample: ReconstructionFilter,
+    upscale: UpscaleFilter,
+) -> Vec<Vector4d> {
+    /*
+        separable resampling of a row-major image, each axis uses the reconstruction filter
+        if it shrinks and the upscale filter otherwise. the result is clamped to valid colors
+        again, negative lobes can overshoot
+    */
+    let taps = |size_src: usize, size_dst: usize| {
+        if size_src > size_dst {
+            calc_axis_taps(size_src, size_dst, downsample.radius(), &|x| {
+                downsample.weight(x)
+            })
+        } else {
+            calc_axis_taps(size_src, size_dst, upscale.radius(), &|x| upscale.weight(x))
+        }
+    };
+    let taps_x = taps(size_src.0, size_dst.0);
+    let taps_y = taps(size_src.1, size_dst.1);
+
+    // rows first, then columns
+    let mut rows = vec![Vector4d::zeros(); size_dst.0 * size_src.1];
+    for y in 0..size_src.1 {
+        for (x, (lower, weights)) in taps_x.iter().enumerate() {
+            let mut mixed = Vector4d::zeros();
+            for (i, w) in weights.iter().enumerate() {
+                mixed += pixels[y * size_src.0 + lower + i] * *w;
+            }
+            rows[y * size_dst.0 + x] = mixed;
+        }
+    }
+
+    let mut resampled = vec![Vector4d::zeros(); size_dst.0 * size_dst.1];
+    for (y, (lower, weights)) in taps_y.iter().enumerate() {
+        for x in 0..size_dst.0 {
+            let mut mixed = Vector4d::zeros();
+            for (i, w) in weights.iter().enumerate() {
+                mixed += rows[(lower + i) * size_dst.0 + x] * *w;
+            }
+            resampled[y * size_dst.0 + x] = Vector4d::new(
+                mixed.x.max(0.0),
+                mixed.y.max(0.0),
+                mixed.z.max(0.0),
+                mixed.u.clamp(0.0, 1.0),
+            );
+        }
+    }
+    resampled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECONSTRUCTION_FILTERS: [ReconstructionFilter; 5] = [
+        ReconstructionFilter::Box,
+        ReconstructionFilter::Tent,
+        ReconstructionFilter::Gaussian,
+        ReconstructionFilter::Mitchell,
+        ReconstructionFilter::Lanczos,
+    ];
+
+    #[test]
+    fn weights_are_symmetric_and_end_at_the_radius() {
+        for filter in RECONSTRUCTION_FILTERS {
+            let radius = filter.radius();
+            for i in 1..40 {
+                let x = i as f64 / 40.0 * radius;
+                // the box is half open, so it is only symmetric off its border
+                if x != 0.5 {
+                    assert!(
+                        (filter.weight(x) - filter.weight(-x)).abs() < 1e-12,
+                        "{}",
+                        filter
+                    );
+                }
+            }
+            assert!(filter.weight(0.0) > 0.0, "{}", filter);
+            if filter != ReconstructionFilter::Gaussian {
+                // the gaussian is cut off where it is small enough to ignore
+                assert!(filter.weight(radius).abs() < 1e-12, "{}", filter);
+                assert_eq!(filter.weight(radius + 0.5), 0.0, "{}", filter);
+            }
+        }
+        assert_eq!(ReconstructionFilter::Box.weight(-0.5), 1.0);
+        assert_eq!(ReconstructionFilter::Box.weight(0.5), 0.0);
+    }
+
+    #[test]
+    fn interpolating_filters_pass_through_the_samples() {
+        let filters: [&dyn Fn(f64) -> f64; 3] = [
+            &|x| ReconstructionFilter::Lanczos.weight(x),
+            &|x| UpscaleFilter::Bilinear.weight(x),
+            &|x| UpscaleFilter::Bicubic.weight(x),
+        ];
+        for weight in filters {
+            assert_eq!(weight(0.0), 1.0);
+            for i in 1..4 {
+                assert!(weight(i as f64).abs() < 1e-12);
+            }
+        }
+        // the Mitchell filter blurs a little and mixes in the direct neighbours
+        assert!((ReconstructionFilter::Mitchell.weight(0.0) - 8.0 / 9.0).abs() < 1e-12);
+        assert!((ReconstructionFilter::Mitchell.weight(1.0) - 1.0 / 18.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn axis_taps_are_normalized() {
+        for filter in RECONSTRUCTION_FILTERS {
+            for taps in [
+                calc_axis_taps(32, 8, filter.radius(), &|x| filter.weight(x)),
+                calc_axis_taps(8, 21, filter.radius(), &|x| filter.weight(x)),
+            ] {
+                for (_, weights) in taps {
+                    assert!(
+                        (weights.iter().sum::<f64>() - 1.0).abs() < 1e-12,
+                        "{}",
+                        filter
+                    );
+                }
+            }
+        }
+        // box downsampling is the plain average of each footprint
+        let taps = calc_axis_taps(12, 3, 0.5, &box_weight);
+        assert_eq!(
+            taps,
+            vec![(0, vec![0.25; 4]), (4, vec![0.25; 4]), (8, vec![0.25; 4])]
+        );
+    }
+
+    #[test]
+    fn resampling_keeps_flat_images() {
+        let color = Vector4d::new(0.2, 0.5, 0.8, 1.0);
+        let pixels = vec![color; 16 * 12];
+        for filter in RECONSTRUCTION_FILTERS {
+            for size_dst in [(4, 3), (40, 30)] {
+                let resampled =
+                    resample(&pixels, (16, 12), size_dst, filter, UpscaleFilter::Bicubic);
+                assert_eq!(resampled.len(), size_dst.0 * size_dst.1);
+                for pixel in resampled {
+                    assert!((pixel - color).length() < 1e-9, "{}", filter);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::graphics::ray::Ray;
+use crate::vectors::{Vector3d, Vector4d};
+
+/*
+    Signed distance fields for implicit shapes, world space.
+
+    A field returns the distance to the closest surface, negative inside. Primitives can be
+    combined with CSG operations, smooth_union blends two shapes with a rounded seam.
+    The rasterizer sphere-traces all SdfShapes of the scene per supersized pixel after the
+    meshes and depth-tests the hits against the z-buffer, so both intersect correctly.
+*/
+
+const MAX_STEPS: usize = 256;
+const HIT_EPSILON: f64 = 1e-4;
+const MAX_DISTANCE: f64 = 1e3;
+const NORMAL_EPSILON: f64 = 1e-4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sdf {
+    Sphere {
+        center: Vector3d,
+        radius: f64,
+    },
+    Box {
+        center: Vector3d,
+        half_extents: Vector3d,
+    },
+    Torus {
+        // ring in the xy plane, like calc_torus
+        center: Vector3d,
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    Capsule {
+        a: Vector3d,
+        b: Vector3d,
+        radius: f64,
+    },
+    Plane {
+        // all points p with normal.dot(p) == offset, normal is normalized
+        normal: Vector3d,
+        offset: f64,
+    },
+    Union(Box<Sdf>, Box<Sdf>),
+    Intersection(Box<Sdf>, Box<Sdf>),
+    Subtraction(Box<Sdf>, Box<Sdf>), // first minus second
+    SmoothUnion(Box<Sdf>, Box<Sdf>, f64),
+}
+
+impl Sdf {
+    pub fn sphere(center: Vector3d, radius: f64) -> Self {
+        Sdf::Sphere { center, radius }
+    }
+
+    pub fn cuboid(center: Vector3d, half_extents: Vector3d) -> Self {
+        Sdf::Box {
+            center,
+            half_extents,
+        }
+    }
+
+    pub fn torus(center: Vector3d, major_radius: f64, minor_radius: f64) -> Self {
+        Sdf::Torus {
+            center,
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    pub fn capsule(a: Vector3d, b: Vector3d, radius: f64) -> Self {
+        Sdf::Capsule { a, b, radius }
+    }
+
+    pub fn plane(normal: Vector3d, offset: f64) -> Self {
+        Sdf::Plane {
+            normal: normal.normalize(),
+            offset,
+        }
+    }
+
+    pub fn union(self, other: Sdf) -> Self {
+        Sdf::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersection(self, other: Sdf) -> Self {
+        Sdf::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn subtract(self, other: Sdf) -> Self {
+        Sdf::Subtraction(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: Sdf, k: f64) -> Self {
+        // k is the size of the blended seam, 0.0 equals union
+        Sdf::SmoothUnion(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn distance(&self, p: Vector3d) -> f64 {
+        match self {
+            Sdf::Sphere { center, radius } => (p - *center).length() - radius,
+            Sdf::Box {
+                center,
+                half_extents,
+            } => {
+                let d = p - *center;
+                let q = Vector3d::new(
+                    d.x.abs() - half_extents.x,
+                    d.y.abs() - half_extents.y,
+                    d.z.abs() - half_extents.z,
+                );
+                let outside = Vector3d::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).length();
+                let inside = q.x.max(q.y).max(q.z).min(0.0);
+                outside + inside
+            }
+            Sdf::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => {
+                let d = p - *center;
+                let ring = (d.x * d.x + d.y * d.y).sqrt() - major_radius;
+                (ring * ring + d.z * d.z).sqrt() - minor_radius
+            }
+            Sdf::Capsule { a, b, radius } => {
+                let pa = p - *a;
+                let ba = *b - *a;
+                let h = (pa.dot(ba) / ba.dot(ba).max(f64::EPSILON)).clamp(0.0, 1.0);
+                (pa - ba * h).length() - radius
+            }
+            Sdf::Plane { normal, offset } => normal.dot(p) - offset,
+            Sdf::Union(a, b) => a.distance(p).min(b.distance(p)),
+            Sdf::Intersection(a, b) => a.distance(p).max(b.distance(p)),
+            Sdf::Subtraction(a, b) => a.distance(p).max(-b.distance(p)),
+            Sdf::SmoothUnion(a, b, k) => smooth_min(a.distance(p), b.distance(p), *k),
+        }
+    }
+
+    pub fn normal(&self, p: Vector3d) -> Vector3d {
+        // gradient by central differences
+        let dx = Vector3d::new(NORMAL_EPSILON, 0.0, 0.0);
+        let dy = Vector3d::new(0.0, NORMAL_EPSILON, 0.0);
+        let dz = Vector3d::new(0.0, 0.0, NORMAL_EPSILON);
+        Vector3d::new(
+            self.distance(p + dx) - self.distance(p - dx),
+            self.distance(p + dy) - self.distance(p - dy),
+            self.distance(p + dz) - self.distance(p - dz),
+        )
+        .normalize()
+    }
+}
+
+pub fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    // polynomial smooth minimum
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b * (1.0 - h) + a * h - k * h * (1.0 - h)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SdfShape {
+    pub sdf: Sdf,
+    pub color: Vector4d,
+}
+
+impl SdfShape {
+    pub fn new(sdf: Sdf, color: Vector4d) -> Self {
+        Self { sdf, color }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SdfHit {
+    pub shape: usize, // index into Scene::sdf_shapes
+    pub t: f64,
+    pub point: Vector3d,
+    pub normal: Vector3d,
+}
+
+pub fn sphere_trace(shapes: &[SdfShape], ray: &Ray) -> Option<SdfHit> {
+    /*
+        marches along the ray by the distance to the closest shape until it is
+        closer than HIT_EPSILON, gives up after MAX_STEPS or beyond MAX_DISTANCE
+    */
+    if shapes.is_empty() {
+        return None;
+    }
+    let mut t = 0.0;
+    for _ in 0..MAX_STEPS {
+        let point = ray.at(t);
+        let (shape, distance) = shapes
+            .iter()
+            .enumerate()
+            .map(|(idx, shape)| (idx, shape.sdf.distance(point)))
+            .min_by(|(_, d_1), (_, d_2)| d_1.total_cmp(d_2))?;
+
+        if distance < HIT_EPSILON * t.max(1.0) {
+            return Some(SdfHit {
+                shape,
+                t,
+                point,
+                normal: shapes[shape].sdf.normal(point),
+            });
+        }
+        t += distance;
+        if t > MAX_DISTANCE {
+            return None;
+        }
+    }
+    None
+}
@@ -1,20 +1,95 @@
+use crate::error::RenderError;
+use crate::graphics::aabb::Aabb;
+use crate::graphics::bvh::Bvh;
+use crate::graphics::material::Material;
+use crate::graphics::ray::{Ray, RayHit};
+use crate::graphics::sdf::SdfShape;
 use crate::util::linspace;
+use crate::vectors::matrices::Matrix4x4;
 use crate::vectors::{Matrix3x3, Vector3d, Vector4d};
+use std::collections::HashMap;
 use std::f64::consts::PI;
 use std::fs;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Scene {
-    pub meshes: Vec<Mesh>,
+    // private, so every mutation goes through a method that keeps the BVH in sync
+    meshes: Vec<Mesh>,
+
+    // implicit shapes, sphere traced by the rasterizer after the meshes
+    pub sdf_shapes: Vec<SdfShape>,
+
+    // acceleration structure over all meshes, kept up to date lazily by bvh()
+    bvh: Bvh,
+    bvh_needs_rebuild: bool,
+    bvh_needs_refit: bool,
 }
 
 impl Scene {
     pub fn new() -> Self {
-        Self { meshes: vec![] }
+        Self {
+            meshes: vec![],
+            sdf_shapes: vec![],
+            bvh: Bvh::default(),
+            bvh_needs_rebuild: false,
+            bvh_needs_refit: false,
+        }
     }
 
     pub fn add_mesh(&mut self, mesh: Mesh) {
         self.meshes.push(mesh);
+        self.bvh_needs_rebuild = true;
+    }
+
+    pub fn meshes(&self) -> &[Mesh] {
+        &self.meshes
+    }
+
+    pub fn mesh_mut(&mut self, mesh_idx: usize) -> &mut Mesh {
+        // the mesh may change geometry, so the BVH is rebuilt before its next use
+        self.bvh_needs_rebuild = true;
+        &mut self.meshes[mesh_idx]
+    }
+
+    pub fn add_sdf_shape(&mut self, shape: SdfShape) {
+        self.sdf_shapes.push(shape);
+    }
+
+    pub fn set_mesh_transform(&mut self, mesh_idx: usize, transform: Matrix4x4) {
+        self.meshes[mesh_idx].transform = transform;
+        self.bvh_needs_refit = true;
+    }
+
+    pub fn invalidate_bvh(&mut self) {
+        // call after editing vertices or faces of a mesh behind a reference kept from mesh_mut
+        self.bvh_needs_rebuild = true;
+    }
+
+    pub fn bvh(&mut self) -> &Bvh {
+        if self.bvh_needs_rebuild {
+            self.rebuild_bvh();
+        } else if self.bvh_needs_refit {
+            self.bvh.refit(&self.meshes);
+            self.bvh_needs_refit = false;
+        }
+        &self.bvh
+    }
+
+    pub fn pick(&mut self, ray: &Ray) -> Option<RayHit> {
+        // bring the BVH up to date first, then borrow it next to the meshes
+        self.bvh();
+        self.intersect(ray)
+    }
+
+    pub fn intersect(&self, ray: &Ray) -> Option<RayHit> {
+        // like pick, but uses the BVH as it is, call bvh() after changing the scene
+        self.bvh.intersect_ray(ray, &self.meshes)
+    }
+
+    pub fn rebuild_bvh(&mut self) {
+        self.bvh = Bvh::build(&self.meshes);
+        self.bvh_needs_rebuild = false;
+        self.bvh_needs_refit = false;
     }
 }
 
@@ -23,80 +98,229 @@ pub struct Mesh {
     pub vertices: Vec<Vector3d>,
     pub faces: Vec<Vec<usize>>,
     pub color: Vector4d,
+    pub material: Material,
 
     pub vertex_merge_radius: f64,
     pub vertex_normals: Vec<Vector3d>,
+
+    // model matrix, object space -> world space. only set through Scene::set_mesh_transform,
+    // which keeps the BVH in sync
+    transform: Matrix4x4,
+
+    // object space bounds of all vertices, see recalc_bounds
+    pub bounds: Aabb,
 }
 
 impl Mesh {
+    #[deprecated(note = "use MeshBuilder::new")]
     pub fn init(color: Vector4d, vertex_merge_radius: f64) -> Self {
         Self::new(vec![], vec![], color, vertex_merge_radius)
     }
+
     pub fn new(
         vertices: Vec<Vector3d>,
         faces: Vec<Vec<usize>>,
         color: Vector4d,
         vertex_merge_radius: f64,
     ) -> Self {
-        Self {
+        let mut mesh = Self {
             vertices,
             faces,
             color,
+            material: Material::default(),
             vertex_merge_radius,
             vertex_normals: Vec::new(),
-        }
+            transform: Matrix4x4::eye(),
+            bounds: Aabb::empty(),
+        };
+        mesh.recalc_bounds();
+        mesh
     }
 
+    #[deprecated(note = "use MeshBuilder::add_face, this rebuilds the vertex lookup on every call")]
     pub fn add_face(&mut self, v1: Vector3d, v2: Vector3d, v3: Vector3d) {
-        let mut new_face = vec![0; 3];
-        let mut found_v1 = false;
-        let mut found_v2 = false;
-        let mut found_v3 = false;
-        for i in 0..self.vertices.len() {
-            if self.vertices[i].equals_roughly(&v1, self.vertex_merge_radius) {
-                new_face[0] = i;
-                found_v1 = true;
-            }
-            if self.vertices[i].equals_roughly(&v2, self.vertex_merge_radius) {
-                new_face[1] = i;
-                found_v2 = true;
-            }
-            if self.vertices[i].equals_roughly(&v3, self.vertex_merge_radius) {
-                new_face[2] = i;
-                found_v3 = true;
+        let mesh = std::mem::replace(
+            self,
+            Mesh::new(vec![], vec![], self.color, self.vertex_merge_radius),
+        );
+        let mut builder = MeshBuilder::from_mesh(mesh);
+        builder.add_face(v1, v2, v3);
+        *self = builder.build();
+    }
+
+    pub fn recalc_vertex_normals(&mut self) {
+        // sum of the normals of all adjacent faces, one pass over the faces
+        let mut normals = vec![Vector3d::zero(); self.vertices.len()];
+
+        for face in &self.faces {
+            let face_normal = (self.vertices[face[1]] - self.vertices[face[0]])
+                .cross(self.vertices[face[2]] - self.vertices[face[0]])
+                .normalize();
+            for (i, vertex) in face.iter().enumerate() {
+                // count a face once, even if it references a vertex twice
+                if !face[..i].contains(vertex) {
+                    normals[*vertex] += face_normal;
+                }
             }
         }
-        if !found_v1 {
-            self.vertices.push(v1);
-            new_face[0] = self.vertices.len() - 1;
+        self.vertex_normals = normals.iter().map(|normal| normal.normalize()).collect();
+    }
+
+    pub fn recalc_bounds(&mut self) {
+        // call after editing vertices directly
+        self.bounds = Aabb::from_points(&self.vertices);
+    }
+
+    pub fn world_bounds(&self) -> Aabb {
+        // bounds of the transformed corners, so no vertex has to be transformed
+        if self.bounds.is_empty() {
+            return Aabb::empty();
+        }
+        let (min, max) = (self.bounds.min, self.bounds.max);
+        let mut world_bounds = Aabb::empty();
+        for corner in [
+            Vector3d::new(min.x, min.y, min.z),
+            Vector3d::new(max.x, min.y, min.z),
+            Vector3d::new(min.x, max.y, min.z),
+            Vector3d::new(max.x, max.y, min.z),
+            Vector3d::new(min.x, min.y, max.z),
+            Vector3d::new(max.x, min.y, max.z),
+            Vector3d::new(min.x, max.y, max.z),
+            Vector3d::new(max.x, max.y, max.z),
+        ] {
+            world_bounds.grow(
+                &self
+                    .transform
+                    .times_vec(Vector4d::from_vector3d(&corner, 1.0))
+                    .truncate_to_3d(),
+            );
         }
-        if !found_v2 {
-            self.vertices.push(v2);
-            new_face[1] = self.vertices.len() - 1;
+        world_bounds
+    }
+
+    pub fn transform(&self) -> &Matrix4x4 {
+        &self.transform
+    }
+
+    pub fn set_material(&mut self, material: Material) {
+        self.material = material;
+    }
+
+    pub fn world_vertex(&self, idx: usize) -> Vector3d {
+        self.transform
+            .times_vec(Vector4d::from_vector3d(&self.vertices[idx], 1.0))
+            .truncate_to_3d()
+    }
+
+    pub fn world_vertices(&self) -> Vec<Vector3d> {
+        (0..self.vertices.len())
+            .map(|idx| self.world_vertex(idx))
+            .collect()
+    }
+
+    pub fn world_normal(&self, normal: &Vector3d) -> Vector3d {
+        // exact for rotations, translations and uniform scaling
+        self.transform
+            .times_vec(Vector4d::from_vector3d(normal, 0.0))
+            .truncate_to_3d()
+            .normalize()
+    }
+}
+
+/*
+    Builds a mesh face by face, merging vertices within vertex_merge_radius of each other.
+    The spatial hash for the lookup only lives as long as the builder, finished meshes
+    don't carry it around.
+*/
+pub struct MeshBuilder {
+    mesh: Mesh,
+
+    // vertex indices per cell, cell size >= vertex_merge_radius
+    vertex_grid: HashMap<(i64, i64, i64), Vec<usize>>,
+}
+
+impl MeshBuilder {
+    pub fn new(color: Vector4d, vertex_merge_radius: f64) -> Self {
+        Self {
+            mesh: Mesh::new(vec![], vec![], color, vertex_merge_radius),
+            vertex_grid: HashMap::new(),
         }
-        if !found_v3 {
-            self.vertices.push(v3);
-            new_face[2] = self.vertices.len() - 1;
+    }
+
+    pub fn from_mesh(mesh: Mesh) -> Self {
+        // continue adding faces to an existing mesh, its vertices take part in the merging
+        let mut builder = Self {
+            mesh,
+            vertex_grid: HashMap::new(),
+        };
+        for idx in 0..builder.mesh.vertices.len() {
+            let cell = builder.grid_cell(&builder.mesh.vertices[idx]);
+            builder.vertex_grid.entry(cell).or_default().push(idx);
         }
-        self.faces.push(new_face);
+        builder
     }
 
-    pub fn recalc_vertex_normals(&mut self) {
-        let mut normals = vec![Vector3d::zero(); self.vertices.len()];
+    pub fn build(self) -> Mesh {
+        self.mesh
+    }
+
+    fn grid_cell(&self, v: &Vector3d) -> (i64, i64, i64) {
+        let cell_size = self.mesh.vertex_merge_radius.max(1e-9);
+        (
+            (v.x / cell_size).floor() as i64,
+            (v.y / cell_size).floor() as i64,
+            (v.z / cell_size).floor() as i64,
+        )
+    }
 
-        for target_vertex in 0..self.vertices.len() {
-            let mut new_normal = Vector3d::zero();
-            for face in &self.faces {
-                if face.contains(&target_vertex) {
-                    // face contains target vertex, calculate face normal and add to new normal
-                    new_normal += (self.vertices[face[1]] - self.vertices[face[0]])
-                        .cross(self.vertices[face[2]] - self.vertices[face[0]])
-                        .normalize();
+    fn find_vertex(&self, v: &Vector3d) -> Option<usize> {
+        /*
+            index of a vertex within vertex_merge_radius of v, the latest one if there are several
+        */
+        let (cx, cy, cz) = self.grid_cell(v);
+        let mut found = None;
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let Some(candidates) = self.vertex_grid.get(&(cx + dx, cy + dy, cz + dz))
+                    else {
+                        continue;
+                    };
+                    for &i in candidates {
+                        if self.mesh.vertices[i].equals_roughly(v, self.mesh.vertex_merge_radius)
+                            && found.is_none_or(|found_i| i > found_i)
+                        {
+                            found = Some(i);
+                        }
+                    }
                 }
             }
-            normals[target_vertex] = new_normal.normalize();
         }
-        self.vertex_normals = normals;
+        found
+    }
+
+    pub fn add_face(&mut self, v1: Vector3d, v2: Vector3d, v3: Vector3d) {
+        // lookup happens before any vertex of this face is pushed
+        let found = [
+            self.find_vertex(&v1),
+            self.find_vertex(&v2),
+            self.find_vertex(&v3),
+        ];
+        let mut new_face = vec![0; 3];
+        for (i, v) in [v1, v2, v3].into_iter().enumerate() {
+            new_face[i] = match found[i] {
+                Some(idx) => idx,
+                None => {
+                    self.mesh.vertices.push(v);
+                    self.mesh.bounds.grow(&v);
+                    let idx = self.mesh.vertices.len() - 1;
+                    let cell = self.grid_cell(&v);
+                    self.vertex_grid.entry(cell).or_default().push(idx);
+                    idx
+                }
+            };
+        }
+        self.mesh.faces.push(new_face);
     }
 }
 
@@ -144,11 +368,18 @@ pub fn calc_torus(
     major_resolution: usize,
     minor_resolution: usize,
     color: &Vector4d,
-) -> Mesh {
-    let phis = linspace(0.0, 2.0 * PI, major_resolution);
-    let thetas = linspace(0.0, 2.0 * PI, minor_resolution);
+) -> Result<Mesh, RenderError> {
+    if major_resolution < 3 || minor_resolution < 3 {
+        return Err(RenderError::InvalidResolution(format!(
+            "torus needs at least 3 segments per ring, found {}x{}",
+            major_resolution, minor_resolution
+        )));
+    }
+
+    let phis = linspace(0.0, 2.0 * PI, major_resolution)?;
+    let thetas = linspace(0.0, 2.0 * PI, minor_resolution)?;
 
-    let mut mesh = Mesh::init(color.clone(), 0.00001);
+    let mut mesh = MeshBuilder::new(*color, 0.00001);
 
     let mut vertices = vec![];
     for major in 0..major_resolution {
@@ -183,13 +414,26 @@ pub fn calc_torus(
         }
     }
 
+    let mut mesh = mesh.build();
     mesh.recalc_vertex_normals();
-    return mesh;
+    Ok(mesh)
 }
 
-pub fn calc_sphere(origin: Vector3d, radius: f64, resolution: usize, color: &Vector4d) -> Mesh {
-    let phis = linspace(0.0, 2.0 * PI, resolution);
-    let thetas = linspace(0.0, PI, resolution);
+pub fn calc_sphere(
+    origin: Vector3d,
+    radius: f64,
+    resolution: usize,
+    color: &Vector4d,
+) -> Result<Mesh, RenderError> {
+    if resolution < 3 {
+        return Err(RenderError::InvalidResolution(format!(
+            "sphere needs a resolution of at least 3, found {}",
+            resolution
+        )));
+    }
+
+    let phis = linspace(0.0, 2.0 * PI, resolution)?;
+    let thetas = linspace(0.0, PI, resolution)?;
 
     let mut vertices = vec![Vector3d::zero(); resolution * resolution];
     for phi_idx in 0..resolution {
@@ -206,7 +450,7 @@ pub fn calc_sphere(origin: Vector3d, radius: f64, resolution: usize, color: &Vec
         }
     }
 
-    let mut mesh = Mesh::init(color.clone(), 0.00001);
+    let mut mesh = MeshBuilder::new(*color, 0.00001);
     for phi_idx in 0..resolution {
         for theta_idx in 0..resolution - 1 {
             if theta_idx == 0 {
@@ -240,11 +484,12 @@ pub fn calc_sphere(origin: Vector3d, radius: f64, resolution: usize, color: &Vec
         mesh.add_face(p1, p3, p2);
     }
 
+    let mut mesh = mesh.build();
     mesh.recalc_vertex_normals();
-    return mesh;
+    Ok(mesh)
 }
 
-pub fn calc_teapot(color: Vector4d, resolution: usize) -> Mesh {
+pub fn calc_teapot(color: Vector4d, resolution: usize) -> Result<Mesh, RenderError> {
     /*
        expects resolution to be either 1, 2 or 3
     */
@@ -258,41 +503,57 @@ pub fn calc_teapot(color: Vector4d, resolution: usize) -> Mesh {
         } else if resolution == 3 {
             "utah_teapot_145620"
         } else {
-            panic!("Have you bot enough vertices already??")
+            return Err(RenderError::InvalidResolution(format!(
+                "teapot resolution must be 1, 2 or 3, found {}",
+                resolution
+            )));
         }
     );
-    let mut mesh = Mesh::init(color, 0.0);
+    let mut mesh = MeshBuilder::new(color, 0.0);
 
-    let contents = fs::read_to_string(file_path).expect("Should have been able to read the file");
-    let faces: Vec<&str> = contents.split("\n\n").collect();
+    let contents = fs::read_to_string(&file_path)?;
     let rot_mat = Matrix3x3::calc_rotation_matrix(Vector3d::new(1.0, 0.0, 0.0), PI / 2.0);
-    for face in faces {
-        let vertices: Vec<&str> = face.split("\n").collect();
-        let v1_string: Vec<&str> = vertices[0].split(" ").collect();
-        let v2_string: Vec<&str> = vertices[1].split(" ").collect();
-        let v3_string: Vec<&str> = vertices[2].split(" ").collect();
-        let v1 = Vector3d::new(
-            v1_string[0].parse::<f64>().unwrap(),
-            v1_string[1].parse::<f64>().unwrap() - 1.0,
-            v1_string[2].parse::<f64>().unwrap(),
-        );
-        let v2 = Vector3d::new(
-            v2_string[0].parse::<f64>().unwrap(),
-            v2_string[1].parse::<f64>().unwrap() - 1.0,
-            v2_string[2].parse::<f64>().unwrap(),
-        );
-        let v3 = Vector3d::new(
-            v3_string[0].parse::<f64>().unwrap(),
-            v3_string[1].parse::<f64>().unwrap() - 1.0,
-            v3_string[2].parse::<f64>().unwrap(),
-        );
+
+    // faces are blocks of three vertex lines, separated by an empty line
+    let mut line_no = 1;
+    for face in contents.split("\n\n") {
+        let lines: Vec<&str> = face.lines().collect();
+        if lines.len() != 3 {
+            return Err(RenderError::Parse {
+                source: file_path,
+                line: line_no,
+                reason: format!("expected 3 vertices per face, found {}", lines.len()),
+            });
+        }
+
+        let mut vertices = [Vector3d::zero(); 3];
+        for (i, line) in lines.iter().enumerate() {
+            let coords = line
+                .split_whitespace()
+                .map(|coord| coord.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>();
+            match coords {
+                Ok(coords) if coords.len() == 3 => {
+                    vertices[i] = Vector3d::new(coords[0], coords[1] - 1.0, coords[2]);
+                }
+                _ => {
+                    return Err(RenderError::Parse {
+                        source: file_path,
+                        line: line_no + i,
+                        reason: format!("expected 3 coordinates, found \"{}\"", line),
+                    });
+                }
+            }
+        }
+        line_no += lines.len() + 1;
 
         mesh.add_face(
-            rot_mat.times_vec(v1),
-            rot_mat.times_vec(v2),
-            rot_mat.times_vec(v3),
+            rot_mat.times_vec(vertices[0]),
+            rot_mat.times_vec(vertices[1]),
+            rot_mat.times_vec(vertices[2]),
         );
     }
+    let mut mesh = mesh.build();
     mesh.recalc_vertex_normals();
-    return mesh;
+    Ok(mesh)
 }
@@ -0,0 +1,176 @@
+use crate::graphics::gbuffer::GBuffer;
+use crate::vectors::matrices::Matrix4x4;
+use crate::vectors::{Vector3d, Vector4d};
+use rand::Rng;
+
+/*
+    Screen-space ambient occlusion.
+
+    Estimates per pixel how much of the hemisphere above the surface is blocked by nearby
+    geometry, using only the camera space positions of the G-buffer and the normal buffer.
+    A kernel of random points in the hemisphere around the normal is projected onto the
+    screen and every point that lies behind the surface stored there counts as occluded.
+    A small tile of random rotations decorrelates the kernel between neighbouring pixels,
+    the blur over one tile removes the resulting noise again.
+*/
+
+// side length of the tile of kernel rotations, also the width of the blur
+const NOISE_SIZE: usize = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ssao {
+    pub radius: f64,           // of the sampled hemisphere, in camera space units
+    pub bias: f64,             // depth tolerance against self occlusion on coarse meshes
+    pub strength: f64,         // 0.0 keeps the flat ambient, 1.0 removes it where fully occluded
+    pub kernel: Vec<Vector3d>, // hemisphere around +z, denser close to the center
+    pub noise: Vec<Vector3d>,  // rotations around the normal, NOISE_SIZE x NOISE_SIZE
+}
+
+impl Default for Ssao {
+    fn default() -> Self {
+        Self::new(16, 1.0)
+    }
+}
+
+impl Ssao {
+    pub fn new(samples: usize, radius: f64) -> Self {
+        /*
+            kernel and noise are drawn once, so the pattern stays put between frames
+            instead of flickering
+        */
+        let samples = samples.max(1);
+        let mut rng = rand::rng();
+        let kernel = (0..samples)
+            .map(|i| {
+                let direction = Vector3d::new(
+                    rng.random::<f64>() * 2.0 - 1.0,
+                    rng.random::<f64>() * 2.0 - 1.0,
+                    rng.random::<f64>(),
+                )
+                .normalize();
+                // more samples near the surface point, they matter most
+                let scale = i as f64 / samples as f64;
+                direction * rng.random::<f64>() * (0.1 + 0.9 * scale * scale)
+            })
+            .collect();
+        let noise = (0..NOISE_SIZE * NOISE_SIZE)
+            .map(|_| {
+                Vector3d::new(
+                    rng.random::<f64>() * 2.0 - 1.0,
+                    rng.random::<f64>() * 2.0 - 1.0,
+                    0.0,
+                )
+            })
+            .collect();
+        Self {
+            radius,
+            bias: 0.025,
+            strength: 1.0,
+            kernel,
+            noise,
+        }
+    }
+
+    pub fn calc_ambient_factors(
+        &self,
+        g_buffer: &GBuffer,
+        normal_buffer: &[Vector3d],
+        size_x: usize,
+        size_y: usize,
+        projection_matrix: &Matrix4x4,
+    ) -> Vec<f64> {
+        /*
+            factor for the ambient term per pixel of the (supersized) buffers, 1.0 for
+            unoccluded surfaces and empty pixels. buffers are row-major with the top row first
+        */
+        let p = projection_matrix;
+        let (half_x, half_y) = ((size_x / 2) as f64, (size_y / 2) as f64);
+
+        let mut occlusion = vec![0.0; size_x * size_y];
+        let mut covered = vec![false; size_x * size_y];
+        for y in 0..size_y {
+            for x in 0..size_x {
+                let idx = (size_y - 1 - y) * size_x + x;
+                let normal = normal_buffer[idx];
+                if !g_buffer.covered[idx] || normal.length() == 0.0 {
+                    continue;
+                }
+                covered[idx] = true;
+                let pos = g_buffer.position[idx];
+
+                // tangent space from the normal and a random rotation of the tile
+                let n = normal.normalize();
+                let random_vec = self.noise[(y % NOISE_SIZE) * NOISE_SIZE + x % NOISE_SIZE];
+                let tangent = random_vec - n * random_vec.dot(n);
+                let tangent = if tangent.length() < 1e-6 {
+                    n.cross(Vector3d::new(1.0, 0.0, 0.0)).normalize()
+                } else {
+                    tangent.normalize()
+                };
+                let bitangent = n.cross(tangent);
+
+                let mut occluded = 0.0;
+                for k in &self.kernel {
+                    let sample = pos + (tangent * k.x + bitangent * k.y + n * k.z) * self.radius;
+
+                    // back onto the screen
+                    let clip = p.times_vec(Vector4d::from_vector3d(&sample, 1.0));
+                    let sample_x = (clip.x / clip.u + 1.0) * half_x;
+                    let sample_y = (clip.y / clip.u + 1.0) * half_y;
+                    if sample_x < 0.0 || sample_y < 0.0 {
+                        continue;
+                    }
+                    let (sample_x, sample_y) = (sample_x as usize, sample_y as usize);
+                    if sample_x >= size_x || sample_y >= size_y {
+                        continue;
+                    }
+                    let sample_idx = (size_y - 1 - sample_y) * size_x + sample_x;
+                    if !g_buffer.covered[sample_idx] {
+                        continue;
+                    }
+
+                    // camera looks down -z, the scene occludes if it is closer than the sample
+                    let scene_z = g_buffer.position[sample_idx].z;
+                    if scene_z >= sample.z + self.bias {
+                        // fade out occluders far in front, e.g. across silhouettes
+                        let range = self.radius / (pos.z - scene_z).abs().max(1e-9);
+                        occluded += smoothstep(range);
+                    }
+                }
+                occlusion[idx] = occluded / self.kernel.len() as f64;
+            }
+        }
+
+        // blur over the noise tile, only between covered pixels
+        let radius = NOISE_SIZE as i64 / 2;
+        let mut factors = vec![1.0; size_x * size_y];
+        for y in 0..size_y as i64 {
+            for x in 0..size_x as i64 {
+                let idx = y as usize * size_x + x as usize;
+                if !covered[idx] {
+                    continue;
+                }
+                let (mut sum, mut count) = (0.0, 0);
+                for y_ in (y - radius)..(y + radius) {
+                    for x_ in (x - radius)..(x + radius) {
+                        if x_ < 0 || y_ < 0 || x_ >= size_x as i64 || y_ >= size_y as i64 {
+                            continue;
+                        }
+                        let idx_ = y_ as usize * size_x + x_ as usize;
+                        if covered[idx_] {
+                            sum += occlusion[idx_];
+                            count += 1;
+                        }
+                    }
+                }
+                factors[idx] = 1.0 - self.strength * sum / count.max(1) as f64;
+            }
+        }
+        factors
+    }
+}
+
+fn smoothstep(x: f64) -> f64 {
+    let x = x.clamp(0.0, 1.0);
+    x * x * (3.0 - 2.0 * x)
+}
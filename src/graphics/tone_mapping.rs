@@ -0,0 +1,58 @@
+use crate::graphics::colors::clamp_color;
+use crate::vectors::Vector4d;
+use std::fmt;
+
+/*
+    Maps linear HDR colors (any value >= 0.0) of the supersized buffer to displayable
+    [0.0, ... 1.0] colors. Applied once per output pixel when resolving into Canvas::buffer,
+    after the color was scaled by the canvas' exposure. Alpha is left untouched.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMapping {
+    Clamp,    // cuts everything above 1.0, the look of the old 8 bit pipeline
+    Reinhard, // c / (1 + c)
+    Aces,     // filmic curve, Narkowicz' fit of the ACES reference transform
+    Exposure, // 1 - exp(-c), like film saturating under exposure
+}
+
+impl fmt::Display for ToneMapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ToneMapping::Clamp => write!(f, "Clamp"),
+            ToneMapping::Reinhard => write!(f, "Reinhard"),
+            ToneMapping::Aces => write!(f, "ACES"),
+            ToneMapping::Exposure => write!(f, "Exposure"),
+        }
+    }
+}
+
+impl ToneMapping {
+    pub fn next(&self) -> Self {
+        // cycles through all operators, handy for toggling in the viewer
+        match self {
+            ToneMapping::Clamp => ToneMapping::Reinhard,
+            ToneMapping::Reinhard => ToneMapping::Aces,
+            ToneMapping::Aces => ToneMapping::Exposure,
+            ToneMapping::Exposure => ToneMapping::Clamp,
+        }
+    }
+
+    pub fn map_channel(&self, c: f64) -> f64 {
+        let c = c.max(0.0);
+        match self {
+            ToneMapping::Clamp => c,
+            ToneMapping::Reinhard => c / (1.0 + c),
+            ToneMapping::Aces => (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14),
+            ToneMapping::Exposure => 1.0 - (-c).exp(),
+        }
+    }
+
+    pub fn apply(&self, color: &Vector4d, exposure: f64) -> Vector4d {
+        clamp_color(Vector4d::new(
+            self.map_channel(color.x * exposure),
+            self.map_channel(color.y * exposure),
+            self.map_channel(color.z * exposure),
+            color.u,
+        ))
+    }
+}
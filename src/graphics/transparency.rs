@@ -0,0 +1,41 @@
+use crate::graphics::colors::{alpha_blend, premultiply};
+use crate::vectors::Vector4d;
+
+/*
+    Order-independent transparency with an A-buffer.
+
+    Fragments with an alpha below 1.0 do not go through the z-buffer right away. They are
+    collected per supersized pixel while all opaque geometry is drawn, without writing depth,
+    normal or ID. Afterwards the fragments of every pixel that are in front of the opaque
+    surface are sorted back to front and blended over it, so the result does not depend on
+    the order meshes or triangles were drawn in.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TransparentFragment {
+    pub idx: usize,      // index into the supersized buffers
+    pub z: f64,          // projected z, larger is further away
+    pub color: Vector4d, // straight alpha, as it comes from the fragment shader
+}
+
+impl TransparentFragment {
+    pub fn new(idx: usize, z: f64, color: Vector4d) -> Self {
+        Self { idx, z, color }
+    }
+}
+
+pub fn resolve_transparent_fragments(
+    fragments: &mut [TransparentFragment],
+    buffer: &mut [Vector4d],
+    z_buffer: &[f64],
+) {
+    // per pixel, furthest first
+    fragments.sort_by(|f_1, f_2| f_1.idx.cmp(&f_2.idx).then(f_2.z.total_cmp(&f_1.z)));
+
+    for fragment in fragments.iter() {
+        // opaque surfaces drawn after the fragment was collected may hide it
+        if fragment.z < z_buffer[fragment.idx] {
+            buffer[fragment.idx] =
+                alpha_blend(&buffer[fragment.idx], &premultiply(&fragment.color));
+        }
+    }
+}
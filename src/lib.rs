@@ -0,0 +1,19 @@
+/*
+    Rusty Ruling Pen, a simple software render engine.
+
+    The viewer binary lives in main.rs, the minifb window backend is only
+    compiled with the "viewer" feature.
+*/
+pub mod backends;
+pub mod error;
+pub mod graphics;
+pub mod util;
+pub mod vectors;
+
+// Public API, so callers can write `rusty_ruling_pen::Canvas` directly
+pub use error::RenderError;
+pub use graphics::{
+    Aabb, Bvh, Camera, Canvas, Mesh, ObjectId, Outline, PointLight, Ray, RayHit, SSAA, Scene,
+    ShadingMode,
+};
+pub use vectors::{IntegerVector2d, Matrix3x3, Matrix4x4, Vector2d, Vector3d, Vector4d};
@@ -1,26 +1,46 @@
 use std::time::Instant;
 
-use minifb::{CursorStyle, Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
-
-use crate::graphics::colors::named_color;
-use crate::graphics::{Camera, Canvas, PointLight, SSAA, calc_sphere, calc_teapot};
-use crate::graphics::{calc_cube, calc_torus};
-use crate::util::{calc_perspective_matrix, clear_console};
-use crate::vectors::{Vector3d, Vector4d};
-use std::f64::consts::PI;
+use rusty_ruling_pen::RenderError;
+#[cfg(feature = "viewer")]
+use rusty_ruling_pen::backends::WindowBackend;
+use rusty_ruling_pen::backends::{
+    Backend, ImageBackend, InputKey, InputMouseButton, TerminalBackend, TerminalMode,
+};
+use rusty_ruling_pen::graphics::colors::named_color;
+use rusty_ruling_pen::graphics::{
+    AovFormat, Camera, Canvas, Fxaa, Msaa, Outline, PathTracer, PointLight, RayHit, RayTracer,
+    ReconstructionFilter, Renderer, SSAA, ShadingMode, Ssao, ToneMapping, UpscaleFilter,
+    calc_teapot, parse_post_effects,
+};
+use rusty_ruling_pen::util::clear_console;
+use rusty_ruling_pen::vectors::{Vector3d, Vector4d};
+use std::env;
 use std::{thread, time};
 
-pub mod graphics;
-pub mod util;
-pub mod vectors;
-
 const SIZE_X: usize = 800;
 const SIZE_Y: usize = 800;
+#[cfg(feature = "viewer")]
 const SCALE: minifb::Scale = minifb::Scale::X1;
 const SSAA: SSAA = SSAA::X4;
-const SHAPE_RESOLUTION: usize = 64;
 const RENDER_SMOOTH: bool = true;
+const TOON_BANDS: usize = 4;
+const OUTLINE_WIDTH: usize = 2;
+const PATH_TRACER_SAMPLES: usize = 64; // headless default without --samples or --time
 const TARGET_FPS: usize = 60;
+
+// viewer key bindings
+const KEY_SSAA_DOWN: InputKey = InputKey::Char('r');
+const KEY_SSAA_UP: InputKey = InputKey::Char('t');
+const KEY_TOON: InputKey = InputKey::Char('c');
+const KEY_TONE_MAPPING: InputKey = InputKey::Char('h');
+const KEY_FXAA: InputKey = InputKey::Char('f');
+const KEY_DEFERRED: InputKey = InputKey::Char('d');
+const KEY_SSAO: InputKey = InputKey::Char('o');
+const KEY_FILTER: InputKey = InputKey::Char('g');
+const KEY_MSAA: InputKey = InputKey::Char('a');
+const KEY_POST_EFFECTS: InputKey = InputKey::Char('p');
+const KEY_RENDERER: InputKey = InputKey::Char('m');
+const KEY_PAN: InputKey = InputKey::LeftCtrl;
 const TARGET_INTERVAL_MILLIS: f64 = 1000.0 / TARGET_FPS as f64;
 
 // fn main() {
@@ -36,37 +56,21 @@ const TARGET_INTERVAL_MILLIS: f64 = 1000.0 / TARGET_FPS as f64;
 */
 
 fn main() -> Result<(), Box<(dyn std::error::Error + 'static)>> {
-    let mut global_timer = Instant::now();
-
-    let mut window = Window::new(
-        "RRP (Rusty Ruling Pen)",
-        SIZE_X,
-        SIZE_Y,
-        WindowOptions {
-            borderless: false,
-            title: true,
-            scale: SCALE,
-            resize: false,
-            scale_mode: minifb::ScaleMode::UpperLeft,
-            topmost: true,
-            transparency: false,
-            none: false,
-        },
-    )?;
-
     let e = Vector3d::new(30.0, 30.0, 30.0);
     let a = Vector3d::zero(); // look at
     let t = Vector3d::new(0.0, 0.0, 1.0); // cam up
 
     let camera = Camera::new(e, a, t, -2.0, 2.0, -2.0, 2.0, 1.0, 10.0);
-    let mut canvas = Canvas::new(
-        SIZE_X,
-        SIZE_Y,
-        named_color("black"),
-        SSAA,
-        RENDER_SMOOTH,
-        camera,
-    );
+    // --transparent renders over a fully transparent background, for compositing the PNG
+    let mut bg_color = named_color("black")?;
+    if env::args().any(|arg| arg == "--transparent") {
+        bg_color.u = 0.0;
+    }
+    let mut canvas = Canvas::new(SIZE_X, SIZE_Y, bg_color, SSAA, RENDER_SMOOTH, camera)?;
+
+    // the ID buffer gives hover info and outlines the selected mesh
+    canvas.set_id_buffer_enabled(true);
+    canvas.set_selection_outline(Some(Outline::new(OUTLINE_WIDTH, canvas.highlight_color)));
 
     // light
     canvas.add_point_light(PointLight::new(
@@ -78,53 +82,320 @@ fn main() -> Result<(), Box<(dyn std::error::Error + 'static)>> {
     canvas.add_point_light(PointLight::new(
         Vector3d::new(-7.0, 5.0, 3.0),
         1.0,
-        named_color("cyan"),
+        named_color("cyan")?,
     ));
 
-    // cube
-    let cube = calc_cube(2.0, Vector3d::zero(), named_color("white"));
-
-    let cube2 = calc_cube(2.0, Vector3d::new(1.0, 1.0, 1.0), named_color("white"));
-    let torus = calc_torus(
-        Vector3d::zero(),
-        2.7,
-        1.2,
-        SHAPE_RESOLUTION * 2,
-        SHAPE_RESOLUTION,
-        &named_color("white"),
-    );
-    let sphere = calc_sphere(
-        Vector3d::zero(),
-        1.3,
-        SHAPE_RESOLUTION,
-        &named_color("white"),
-    );
-    let teapot = calc_teapot(named_color("white"), 1);
-
-    // canvas.add_mesh(torus);
-    // canvas.add_mesh(sphere);
-    // canvas.add_mesh(cube);
+    let teapot = calc_teapot(named_color("white")?, 1)?;
     canvas.add_mesh(teapot);
 
+    let args: Vec<String> = env::args().collect();
+    if args.iter().any(|arg| arg == "--raytrace") {
+        canvas.set_renderer(Renderer::RayTracer(RayTracer::default()));
+    }
+    if args.iter().any(|arg| arg == "--pathtrace") {
+        let mut path_tracer = PathTracer {
+            max_samples: flag_value::<usize>(&args, "--samples")?,
+            time_budget: flag_value::<f64>(&args, "--time")?.map(time::Duration::from_secs_f64),
+            ..PathTracer::default()
+        };
+        if path_tracer.max_samples.is_none() && path_tracer.time_budget.is_none() {
+            path_tracer.max_samples = Some(PATH_TRACER_SAMPLES);
+        }
+        canvas.set_renderer(Renderer::PathTracer(path_tracer));
+    }
+    if let Some(spec) = args
+        .iter()
+        .position(|arg| arg == "--post")
+        .and_then(|idx| args.get(idx + 1))
+    {
+        // e.g. --post "bloom, vignette:0.4, grain"
+        canvas.set_post_effects(parse_post_effects(spec)?);
+    }
+    if let Some(fac) = flag_value::<f64>(&args, "--ssaa")? {
+        // scale per axis, e.g. 1.5 for 2.25 samples per pixel or 0.5 to upscale
+        canvas.set_ssaa(SSAA::Factor(fac))?;
+    }
+    if let Some(name) = args
+        .iter()
+        .position(|arg| arg == "--ssaa-filter")
+        .and_then(|idx| args.get(idx + 1))
+    {
+        canvas.set_ssaa_filter(ReconstructionFilter::from_name(name)?);
+    }
+    if let Some(name) = args
+        .iter()
+        .position(|arg| arg == "--upscale")
+        .and_then(|idx| args.get(idx + 1))
+    {
+        canvas.set_upscale_filter(UpscaleFilter::from_name(name)?);
+    }
+    if args.iter().any(|arg| arg == "--deferred") {
+        canvas.set_deferred(true);
+    }
+    if args.iter().any(|arg| arg == "--ssao") {
+        canvas.set_ssao(Some(Ssao::default()));
+    }
+    if args.iter().any(|arg| arg == "--fxaa") {
+        canvas.set_fxaa(Some(Fxaa::default()));
+    }
+    if let Some(samples) = flag_value::<usize>(&args, "--msaa")? {
+        // 4 (rotated grid), 8 (sparse) or 16 (ordered grid) samples per pixel
+        canvas.set_msaa(Some(match samples {
+            4 => Msaa::rotated_grid(),
+            8 => Msaa::sparse_8(),
+            16 => Msaa::ordered_grid(4),
+            _ => {
+                return Err(RenderError::InvalidArgument(format!(
+                    "--msaa expects 4, 8 or 16 samples, got {}",
+                    samples
+                ))
+                .into());
+            }
+        }));
+    }
+    // --aovs "out_{aov}.exr" writes depth, normal, albedo and ID passes of the last frame
+    let aov_path = args
+        .iter()
+        .position(|arg| arg == "--aovs")
+        .and_then(|idx| args.get(idx + 1));
+    let aov_format = match args
+        .iter()
+        .position(|arg| arg == "--aov-format")
+        .and_then(|idx| args.get(idx + 1))
+    {
+        Some(name) => Some(AovFormat::from_name(name)?),
+        None => aov_path
+            .map(|path| AovFormat::from_path(path))
+            .transpose()?,
+    };
+    if aov_path.is_some() {
+        canvas.set_aovs_enabled(true);
+    }
+    if args.iter().any(|arg| arg == "--no-srgb") {
+        // shade on the raw color values, the flatter look of older versions
+        canvas.set_srgb_enabled(false);
+    }
+    if let Some(exposure) = flag_value::<f64>(&args, "--exposure")? {
+        canvas.set_tone_mapping(ToneMapping::Aces, exposure);
+    }
+    let console;
+    // set when the canvas already holds the first frame, so run presents it without rendering
+    let mut frame_ready = false;
+    let mut backend: Box<dyn Backend> = if args.iter().any(|arg| arg == "--terminal") {
+        // the detected size and color mode can be overridden, e.g. when ssh hides them
+        let mut terminal = TerminalBackend::from_env();
+        if let Some(cols) = flag_value::<usize>(&args, "--cols")? {
+            terminal.cols = cols.max(1);
+        }
+        if let Some(rows) = flag_value::<usize>(&args, "--rows")? {
+            terminal.rows = rows.max(1);
+        }
+        if args.iter().any(|arg| arg == "--truecolor") {
+            terminal.mode = TerminalMode::TrueColor;
+        } else if args.iter().any(|arg| arg == "--ascii") {
+            terminal.mode = TerminalMode::Ascii;
+        }
+        console = ConsoleStatus::Backend;
+        Box::new(terminal)
+    } else if let Some(path) = args
+        .iter()
+        .position(|arg| arg == "--image")
+        .map(|idx| args.get(idx + 1).map_or("render.png", |path| path.as_str()))
+        // --aovs alone renders headless as well, the beauty image goes to the default path
+        .or(aov_path.map(|_| "render.png"))
+    {
+        let frames = flag_value::<usize>(&args, "--frames")?.unwrap_or(1);
+
+        // headless path tracing accumulates until converged before the first frame is written
+        if let Renderer::PathTracer(path_tracer) = canvas.renderer {
+            while !path_tracer.is_converged(&canvas) {
+                canvas.render();
+                println!(
+                    "{} samples in {:.1} s",
+                    canvas.accumulated_samples,
+                    canvas.accumulation_time.as_secs_f64()
+                );
+                frame_ready = true;
+            }
+        }
+        console = ConsoleStatus::FirstLine;
+        Box::new(ImageBackend::new(path, frames))
+    } else {
+        console = ConsoleStatus::Full;
+        window_backend()?
+    };
+
+    run(&mut canvas, backend.as_mut(), console, frame_ready)?;
+    if let (Some(path), Some(format)) = (aov_path, aov_format) {
+        canvas.save_aovs(path, format)?;
+    }
+    Ok(())
+}
+
+// where the per frame statistics are printed, the library itself never prints them
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ConsoleStatus {
+    Backend,   // the backend shows them itself, e.g. below the terminal image
+    Full,      // console cleared and reprinted every frame, next to the window
+    FirstLine, // one line per written frame for headless runs
+}
+
+fn print_status(console: ConsoleStatus, frame: usize, lines: &[String]) {
+    match console {
+        ConsoleStatus::Backend => {}
+        ConsoleStatus::Full => {
+            clear_console();
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+        ConsoleStatus::FirstLine => {
+            if let Some(first) = lines.first() {
+                println!("frame {}: {}", frame, first);
+            }
+        }
+    }
+}
+
+fn flag_value<T: std::str::FromStr>(args: &[String], flag: &str) -> Result<Option<T>, RenderError> {
+    // value following flag, an error if it is missing or does not parse
+    let Some(idx) = args.iter().position(|arg| arg == flag) else {
+        return Ok(None);
+    };
+    let value = args
+        .get(idx + 1)
+        .ok_or_else(|| RenderError::InvalidArgument(format!("{} expects a value", flag)))?;
+    value.parse::<T>().map(Some).map_err(|_| {
+        RenderError::InvalidArgument(format!("invalid value \"{}\" for {}", value, flag))
+    })
+}
+
+#[cfg(feature = "viewer")]
+fn window_backend() -> Result<Box<dyn Backend>, Box<dyn std::error::Error>> {
+    Ok(Box::new(WindowBackend::new(
+        "RRP (Rusty Ruling Pen)",
+        SIZE_X,
+        SIZE_Y,
+        SCALE,
+    )?))
+}
+
+#[cfg(not(feature = "viewer"))]
+fn window_backend() -> Result<Box<dyn Backend>, Box<dyn std::error::Error>> {
+    Err("built without the \"viewer\" feature, use --terminal or --image <path>".into())
+}
+
+fn run(
+    canvas: &mut Canvas,
+    backend: &mut dyn Backend,
+    console: ConsoleStatus,
+    mut frame_ready: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
     let mut prev_mouse_pos = (0.0 as f32, 0.0 as f32);
-    while window.is_open() && !window.is_key_down(Key::Enter) && !window.is_key_down(Key::Space) {
-        global_timer = Instant::now();
+    let mut prev_left_down = false;
+    let mut last_pick: Option<RayHit> = None;
+    let mut frame = 0;
+    while backend.is_open() {
+        let global_timer = Instant::now();
+
+        let input = backend.poll_input();
+        if input.is_key_down(InputKey::Enter) || input.is_key_down(InputKey::Space) {
+            break;
+        }
 
-        let (g, u, v, w) = canvas.camera.calc_guvw();
+        let (_, u, v, w) = canvas.camera.calc_guvw();
 
         // handle keyboard and mouse
 
-        if window.is_key_pressed(Key::R, KeyRepeat::No) {
-            canvas.decrease_ssaa();
+        if input.is_key_pressed(KEY_SSAA_DOWN) {
+            canvas.decrease_ssaa()?;
+        }
+        if input.is_key_pressed(KEY_SSAA_UP) {
+            canvas.increase_ssaa()?;
         }
-        if window.is_key_pressed(Key::T, KeyRepeat::No) {
-            canvas.increase_ssaa();
+        if input.is_key_pressed(KEY_TOON) {
+            // toggle cel shading with outlines
+            match canvas.shading_mode {
+                ShadingMode::Phong => {
+                    canvas.set_shading_mode(ShadingMode::Toon { bands: TOON_BANDS });
+                    canvas.set_outline(Some(Outline::new(OUTLINE_WIDTH, named_color("black")?)));
+                }
+                ShadingMode::Toon { .. } => {
+                    canvas.set_shading_mode(ShadingMode::Phong);
+                    canvas.set_outline(None);
+                }
+            }
+        }
+        if input.is_key_pressed(KEY_TONE_MAPPING) {
+            // cycle through the tone mapping operators
+            canvas.set_tone_mapping(canvas.tone_mapping.next(), canvas.exposure);
+        }
+        if input.is_key_pressed(KEY_FXAA) {
+            // toggle FXAA, cheap edge smoothing that also works with SSAA off
+            canvas.set_fxaa(match canvas.fxaa {
+                Some(_) => None,
+                None => Some(Fxaa::default()),
+            });
+        }
+        if input.is_key_pressed(KEY_DEFERRED) {
+            // toggle deferred shading, lights every visible pixel once
+            canvas.set_deferred(!canvas.deferred);
+        }
+        if input.is_key_pressed(KEY_SSAO) {
+            // toggle ambient occlusion, darkens creases and contact areas
+            canvas.set_ssao(match canvas.ssao {
+                Some(_) => None,
+                None => Some(Ssao::default()),
+            });
+        }
+        if input.is_key_pressed(KEY_FILTER) {
+            // cycle the filter of the current mode, reconstruction or upscaling
+            if canvas.ssaa.fac() < 1.0 {
+                canvas.set_upscale_filter(canvas.upscale_filter.next());
+            } else {
+                canvas.set_ssaa_filter(canvas.ssaa_filter.next());
+            }
+        }
+        if input.is_key_pressed(KEY_MSAA) {
+            // cycle through MSAA off, 4x rotated grid and 8x sparse
+            canvas.set_msaa(match canvas.msaa.as_ref().map(Msaa::sample_count) {
+                None => Some(Msaa::rotated_grid()),
+                Some(4) => Some(Msaa::sparse_8()),
+                Some(_) => None,
+            });
+        }
+        if input.is_key_pressed(KEY_POST_EFFECTS) {
+            // toggle the post effect chain
+            canvas.set_post_effects_enabled(!canvas.post_effects_enabled);
+        }
+        if input.is_key_pressed(KEY_RENDERER) {
+            // cycle through rasterized, ray traced and path traced output
+            match canvas.renderer {
+                Renderer::Rasterizer => {
+                    canvas.set_renderer(Renderer::RayTracer(RayTracer::default()))
+                }
+                Renderer::RayTracer(_) => {
+                    canvas.set_renderer(Renderer::PathTracer(PathTracer::default()))
+                }
+                Renderer::PathTracer(_) => canvas.set_renderer(Renderer::Rasterizer),
+            }
         }
         // handle mouse input
-        let cur_mouse_pos = window.get_mouse_pos(MouseMode::Pass).unwrap();
+        let cur_mouse_pos = input.mouse_pos.unwrap_or(prev_mouse_pos);
+        let mouse_delta = (
+            cur_mouse_pos.0 - prev_mouse_pos.0,
+            cur_mouse_pos.1 - prev_mouse_pos.1,
+        );
 
-        if window.get_mouse_down(MouseButton::Left) {
-            if window.is_key_pressed(Key::LeftCtrl, KeyRepeat::Yes) {
+        let left_down = input.is_mouse_down(InputMouseButton::Left);
+        if left_down && !prev_left_down && !input.is_key_down(KEY_PAN) {
+            // click selects the mesh under the cursor
+            last_pick = canvas.select_at(cur_mouse_pos.0 as f64, cur_mouse_pos.1 as f64);
+        }
+        prev_left_down = left_down;
+
+        if input.is_mouse_down(InputMouseButton::Left) {
+            if input.is_key_down(KEY_PAN) {
                 // pan mode
 
                 // canvas.camera.e.x += 1.0;
@@ -133,15 +404,17 @@ fn main() -> Result<(), Box<(dyn std::error::Error + 'static)>> {
         }
         prev_mouse_pos = cur_mouse_pos;
 
-        // render loop
-        canvas.reset();
-        canvas.reset_z_buffer();
+        // render loop, unless the first frame was rendered up front
+        if !std::mem::take(&mut frame_ready) {
+            canvas.reset();
+            canvas.reset_z_buffer();
 
-        // finally, render scene
-        canvas.render_scene_to_buffer();
+            // finally, render scene
+            canvas.render();
+        }
 
-        // update minifb with new buffer
-        window.update_with_buffer(&canvas.buffer, canvas.size_x, canvas.size_y)?;
+        // hand the new buffer to the backend
+        backend.present(&canvas.buffer, canvas.size_x, canvas.size_y)?;
 
         // compute sleep duration to reach target fps
         let render_time_millis = global_timer.elapsed().as_millis();
@@ -150,32 +423,90 @@ fn main() -> Result<(), Box<(dyn std::error::Error + 'static)>> {
         let interval = (render_time_millis as f64 + delta_to_target_interval) / 1000.0;
 
         // print statistics:
-        clear_console();
-
-        println!("{} FPS", 1.0 / interval);
-        println!("Rendertime: {} ms", interval,);
-        println!("Render config:");
-        println!(
-            "  Image size: \n       {}x{} pixels, {} pixels in total",
-            canvas.size_x,
-            canvas.size_y,
-            canvas.buffer.len()
-        );
-        println!("  Antialiasing: \n        {}", canvas.ssaa);
-        println!(
-            "       {}x{} pixels, {} pixels in total",
-            canvas.size_x_supersized,
-            canvas.size_y_supersized,
-            canvas.buffer_supersized.len()
-        );
-        println!(
-            "Camera: \n    eye: {}\n    ufw: {}, {}, {}",
-            canvas.camera.e, u, v, w
-        );
-        println!(
-            "Mouse delta:\n     {}, {}",
-            cur_mouse_pos.0, cur_mouse_pos.0
-        );
+        let status = [
+            format!("{} FPS", 1.0 / interval),
+            format!("Rendertime: {} ms", interval),
+            "Render config:".to_string(),
+            format!(
+                "  Image size: \n       {}x{} pixels, {} pixels in total",
+                canvas.size_x,
+                canvas.size_y,
+                canvas.buffer.len()
+            ),
+            format!("  Renderer: \n        {}", canvas.renderer),
+            format!(
+                "       {} samples accumulated in {:.1} s",
+                canvas.accumulated_samples,
+                canvas.accumulation_time.as_secs_f64()
+            ),
+            format!(
+                "  Shading: \n        {}, {}{}",
+                canvas.shading_mode,
+                if canvas.deferred {
+                    "deferred"
+                } else {
+                    "forward"
+                },
+                if canvas.ssao.is_some() { " + SSAO" } else { "" }
+            ),
+            format!(
+                "  Tone mapping: \n        {}, exposure {}",
+                canvas.tone_mapping, canvas.exposure
+            ),
+            format!(
+                "  Post effects: \n        {}{}",
+                canvas
+                    .post_effects
+                    .iter()
+                    .map(|effect| effect.name())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                if canvas.post_effects_enabled {
+                    ""
+                } else {
+                    " (off)"
+                }
+            ),
+            format!(
+                "  Antialiasing: \n        {}, {}{}{}",
+                canvas.ssaa,
+                if canvas.ssaa.fac() < 1.0 {
+                    canvas.upscale_filter.to_string()
+                } else {
+                    canvas.ssaa_filter.to_string()
+                },
+                match &canvas.msaa {
+                    Some(msaa) => format!(" + {}", msaa),
+                    None => String::new(),
+                },
+                if canvas.fxaa.is_some() { " + FXAA" } else { "" }
+            ),
+            format!(
+                "       {}x{} pixels, {} pixels in total",
+                canvas.size_x_supersized,
+                canvas.size_y_supersized,
+                canvas.buffer_supersized.len()
+            ),
+            format!("Culling: \n    {}", canvas.stats),
+            format!(
+                "Selection: \n    {}",
+                last_pick.map_or("none".to_string(), |hit| hit.to_string())
+            ),
+            format!(
+                "Hover: \n    {}",
+                canvas
+                    .id_at(cur_mouse_pos.0 as usize, cur_mouse_pos.1 as usize)
+                    .map_or("none".to_string(), |id| id.to_string())
+            ),
+            format!(
+                "Camera: \n    eye: {}\n    ufw: {}, {}, {}",
+                canvas.camera.e, u, v, w
+            ),
+            format!("Mouse delta:\n     {}, {}", mouse_delta.0, mouse_delta.1),
+        ];
+        frame += 1;
+        backend.show_status(&status);
+        print_status(console, frame, &status);
         thread::sleep(time::Duration::from_millis(delta_to_target_interval as u64));
     }
 
@@ -1,3 +1,4 @@
+use crate::error::RenderError;
 use crate::vectors::Vector4d;
 use crate::vectors::matrices::Matrix4x4;
 
@@ -5,15 +6,20 @@ pub fn clear_console() {
     print!("{}[2J", 27 as char);
 }
 
-pub fn interpolate1d(mut start: f64, mut end: f64, factor: f64) -> f64 {
-    assert!(factor >= 0.0 && factor <= 1.0, "Cannot clamp yet");
+pub fn interpolate1d(mut start: f64, mut end: f64, factor: f64) -> Result<f64, RenderError> {
+    if !(0.0..=1.0).contains(&factor) {
+        return Err(RenderError::InvalidArgument(format!(
+            "interpolation factor {} outside of [0, 1]",
+            factor
+        )));
+    }
 
     if start > end {
         (end, start) = (start, end);
     }
 
     let delta = end - start;
-    return start + factor * delta;
+    Ok(start + factor * delta)
 }
 
 pub fn calc_perspective_matrix(l: f64, r: f64, b: f64, t: f64, n: f64, f: f64) -> Matrix4x4 {
@@ -34,19 +40,24 @@ pub fn calc_ortho_matrix(l: f64, r: f64, b: f64, t: f64, n: f64, f: f64) -> Matr
     );
 }
 
-pub fn linspace(start: f64, end: f64, n: usize) -> Vec<f64> {
+pub fn linspace(start: f64, end: f64, n: usize) -> Result<Vec<f64>, RenderError> {
     /*
     [start, end)
     Including start, excluding end
      */
-    assert!(start <= end);
+    if start > end {
+        return Err(RenderError::InvalidArgument(format!(
+            "linspace start {} is greater than end {}",
+            start, end
+        )));
+    }
     let mut ret = vec![0.0; n];
     let delta = (end - start) / n as f64;
     for i in 0..n {
         ret[i] = start + i as f64 * delta;
     }
 
-    return ret;
+    Ok(ret)
 }
 
 pub fn clamp(val: f64) -> f64 {
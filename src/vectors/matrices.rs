@@ -182,6 +182,35 @@ impl Matrix4x4 {
         );
     }
 
+    pub fn times_matrix(&self, other: &Matrix4x4) -> Self {
+        let cols = other.transpose();
+        Self::from_vecs(
+            cols.times_vec(self.a),
+            cols.times_vec(self.b),
+            cols.times_vec(self.c),
+            cols.times_vec(self.d),
+        )
+    }
+
+    pub fn translation(offset: Vector3d) -> Self {
+        Self::from_vecs(
+            Vector4d::new(1.0, 0.0, 0.0, offset.x),
+            Vector4d::new(0.0, 1.0, 0.0, offset.y),
+            Vector4d::new(0.0, 0.0, 1.0, offset.z),
+            Vector4d::new(0.0, 0.0, 0.0, 1.0),
+        )
+    }
+
+    pub fn from_matrix3x3(m: &Matrix3x3) -> Self {
+        // linear part only, no translation
+        Self::from_vecs(
+            Vector4d::from_vector3d(&m.a, 0.0),
+            Vector4d::from_vector3d(&m.b, 0.0),
+            Vector4d::from_vector3d(&m.c, 0.0),
+            Vector4d::new(0.0, 0.0, 0.0, 1.0),
+        )
+    }
+
     pub fn transpose(&self) -> Self {
         return Self::from_floats(
             self.a.x, self.b.x, self.c.x, self.d.x, self.a.y, self.b.y, self.c.y, self.d.y,